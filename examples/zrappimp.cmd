@@ -2,11 +2,16 @@
 REM Erstt med dina uppgifter
 set DINKASSA_USERNAME=
 set DINKASSA_PASSWORD=
-REM set DINKASSA_INTEGRATOR_ID=
-REM set DINKASSA_MACHINE_ID=
-REM set DINKASSA_MACHINE_KEY=
+REM Eller ls lsenordet frn en fil istllet fr att skriva det hr
+REM set DINKASSA_PASSWORD_FILE=%APPDATA%\esbokio\dinkassa-password
+REM Alternativt maskininloggning, erstter anvndarnamn och lsenord
+set DINKASSA_INTEGRATOR_ID=
+set DINKASSA_MACHINE_ID=
+set DINKASSA_MACHINE_KEY=
 set BOKIO_API_TOKEN=
 set BOKIO_COMPANY_ID=
+REM Konto fr resavrundning, t.ex. 3740
+set BOKIO_ROUNDING_ACCOUNT=
 set RUST_BACKTRACE=1
 esbokio %*
 
@@ -6,11 +6,17 @@ SCRIPT_DIR="$(dirname $(readlink -f "$0"))"
 # Ersätt med dina uppgifter
 export DINKASSA_USERNAME=''
 export DINKASSA_PASSWORD=''
-#export DINKASSA_INTEGRATOR_ID=''
-#export DINKASSA_MACHINE_ID=''
-#export DINKASSA_MACHINE_KEY=''
+# Eller läs lösenordet från en fil eller ett kommando istället för att skriva det här
+# export DINKASSA_PASSWORD_COMMAND='pass show dinkassa'
+# Alternativt maskininloggning, ersätter användarnamn och lösenord
+export DINKASSA_INTEGRATOR_ID=''
+export DINKASSA_MACHINE_ID=''
+export DINKASSA_MACHINE_KEY=''
 export BOKIO_API_TOKEN=''
+# export BOKIO_API_TOKEN_FILE="$HOME/.config/esbokio/bokio-token"
 export BOKIO_COMPANY_ID=''
+# Konto för öresavrundning, t.ex. 3740
+export BOKIO_ROUNDING_ACCOUNT=''
 export RUST_BACKTRACE=1
 
 esbokio=esbokio
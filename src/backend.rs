@@ -0,0 +1,54 @@
+use crate::bokio::{Account, JournalEntry, UploadResponse};
+use crate::error::Error;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// A journal entry to create. Bokio gets the title, date and lines. The names are kept
+/// by a [`Ledger`], which has no company or chart of accounts of its own.
+///
+/// [`Ledger`]: crate::ledger::Ledger
+pub struct NewJournalEntry {
+    pub title: String,
+    /// YYYY-MM-DD
+    pub date: String,
+    pub items: Vec<NewJournalLine>,
+    pub company_name: String,
+}
+
+pub struct NewJournalLine {
+    pub account: i32,
+    pub debit: Decimal,
+    pub credit: Decimal,
+    pub name: String,
+}
+
+/// The accounting system the Z-reports are booked in, Bokio or a [`Ledger`] in local
+/// files. Entries are created from a [`NewJournalEntry`], the other types are those
+/// of the Bokio API.
+///
+/// [`Ledger`]: crate::ledger::Ledger
+pub trait AccountingBackend {
+    /// For messages, e.g. "Bokio".
+    fn name(&self) -> String;
+
+    /// Entries with titles starting with "Kassa" between the dates, newest first.
+    fn list_journal(&self, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>)
+                    -> Result<Vec<JournalEntry>, Error>;
+
+    /// `None` when the entry does not exist.
+    fn get_journal_entry(&self, journal_entry_id: &str) -> Result<Option<JournalEntry>, Error>;
+
+    fn create_journal_entry(&self, entry: &NewJournalEntry) -> Result<JournalEntry, Error>;
+
+    /// Reverses an entry and returns the reversing entry.
+    fn reverse_journal_entry(&self, journal_entry_id: &str) -> Result<JournalEntry, Error>;
+
+    /// Attaches a file to an entry.
+    fn upload(&self, filename: &str, content_type: &str, journal_entry_id: &str) -> Result<UploadResponse, Error>;
+
+    fn list_uploads(&self, journal_entry_id: &str) -> Result<Vec<UploadResponse>, Error>;
+
+    /// The chart of accounts, or `None` when there is none to check accounts against.
+    /// `refresh` asks for a fresh copy where it is cached.
+    fn chart_of_accounts(&self, refresh: bool) -> Result<Option<Vec<Account>>, Error>;
+}
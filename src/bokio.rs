@@ -1,12 +1,19 @@
-use crate::utils::{APPLICATION_JSON, PageReq};
-use chrono::NaiveDate;
-use http::header::{ACCEPT, AUTHORIZATION};
+use crate::backend::{AccountingBackend, NewJournalEntry};
+use crate::error::{check_status, Error};
+use crate::retry::RetryPolicy;
+use crate::utils::{APPLICATION_JSON, CONNECT_TIMEOUT, ESBOKIO_USER_AGENT, PageReq, RESPONSE_TIMEOUT};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use http::header::AUTHORIZATION;
+use http::{HeaderValue, Request, Response};
 use mime::Mime;
 use multipart::client::lazy::Multipart;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use ureq::Error;
+use std::sync::Mutex;
+use ureq::middleware::{Middleware, MiddlewareNext};
+use ureq::{Agent, Body, SendBody};
 
 pub const BOKIO_API_URL: &str = "https://api.bokio.se/v1";
 
@@ -20,7 +27,7 @@ pub struct JournalEntryAccount {
     pub credit: Decimal,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[allow(unused)]
 pub struct JournalEntry {
     pub id: String,
@@ -35,6 +42,14 @@ pub struct JournalEntry {
     pub reversed_by_journal_entry_id: Option<String>,
 }
 
+impl JournalEntry {
+    pub fn parse_date(&self) -> Result<NaiveDate, Error> {
+        self.date.parse::<NaiveDate>().map_err(|e| {
+            Error::Parse(format!("datum {} för verifikat {}: {}", self.date, self.journal_entry_number, e))
+        })
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[allow(unused)]
 pub struct JournalEntryListing {
@@ -63,7 +78,21 @@ pub struct CreateJournal {
     pub items: Vec<CreateJournalAccount>,
 }
 
-#[derive(Debug, Deserialize)]
+impl From<&NewJournalEntry> for CreateJournal {
+    fn from(entry: &NewJournalEntry) -> Self {
+        CreateJournal {
+            title: entry.title.clone(),
+            date: entry.date.clone(),
+            items: entry
+                .items
+                .iter()
+                .map(|item| CreateJournalAccount { account: item.account, debit: item.debit, credit: item.credit })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[allow(unused)]
 pub struct UploadResponse {
     pub id: String,
@@ -74,36 +103,168 @@ pub struct UploadResponse {
     pub journal_entry_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[allow(unused)]
+pub struct UploadListing {
+    #[serde(rename = "totalItems")]
+    pub total_items: u32,
+    #[serde(rename = "totalPages")]
+    pub total_pages: u32,
+    #[serde(rename = "currentPage")]
+    pub current_page: u32,
+    pub items: Vec<UploadResponse>,
+}
+
+/// An account in the company's chart of accounts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[allow(unused)]
+pub struct Account {
+    pub account: i32,
+    pub name: String,
+}
+
+/// Chart of accounts saved between runs, see [`Bokio::chart_of_accounts`].
+#[derive(Deserialize, Serialize)]
+struct AccountsCache {
+    company_id: String,
+    fetched: String,
+    accounts: Vec<Account>,
+}
+
+/// How long a saved chart of accounts is used before it is fetched again.
+const ACCOUNTS_CACHE_TTL: TimeDelta = TimeDelta::hours(24);
+
+impl AccountsCache {
+    fn load(path: &Path, company_id: &str) -> Option<Vec<Account>> {
+        let json = std::fs::read(path).ok()?;
+        let cache: AccountsCache = serde_json::from_slice(&json).ok()?;
+        let fetched = DateTime::parse_from_rfc3339(&cache.fetched).ok()?;
+        if cache.company_id != company_id || Utc::now() - fetched.with_timezone(&Utc) > ACCOUNTS_CACHE_TTL {
+            return None;
+        }
+        Some(cache.accounts)
+    }
+
+    fn save(path: &Path, company_id: &str, accounts: &[Account]) {
+        let cache = AccountsCache {
+            company_id: company_id.to_string(),
+            fetched: Utc::now().to_rfc3339(),
+            accounts: accounts.to_vec(),
+        };
+        let json = serde_json::to_vec_pretty(&cache).unwrap();
+        path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(path, json))
+            .inspect_err(|e| eprintln!("Kunde inte spara kontoplan i {}: {}", path.display(), e))
+            .ok();
+    }
+}
+
+/// Adds the API token to each request.
+struct BokioAuth {
+    auth_header: HeaderValue,
+}
+
+impl Middleware for BokioAuth {
+    fn handle(&self, mut req: Request<SendBody>, next: MiddlewareNext)
+              -> Result<Response<Body>, ureq::Error> {
+        req.headers_mut().insert(AUTHORIZATION, self.auth_header.clone());
+        next.handle(req)
+    }
+}
+
 pub struct Bokio {
     base_url: String,
     company_id: String,
-    auth_header: String,
+    agent: Agent,
+    pub retry: RetryPolicy,
+    /// File where the chart of accounts is saved between runs.
+    pub accounts_cache: Option<PathBuf>,
+    /// The chart of accounts and whether it was fetched from the API in this run.
+    accounts: Mutex<Option<(Vec<Account>, bool)>>,
 }
 
 impl Bokio {
-    pub fn new(base_url: &str, company_id: &str, token: &str) -> Self {
-        Self {
+    pub fn new(base_url: &str, company_id: &str, token: &str) -> Result<Self, Error> {
+        let mut auth_header = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| Error::Validation("Ogiltigt tecken i Bokio API token".to_string()))?;
+        auth_header.set_sensitive(true);
+
+        // Connections are kept alive in the agent's pool and reused between pages.
+        let agent: Agent = Agent::config_builder()
+            .accept(APPLICATION_JSON)
+            .user_agent(ESBOKIO_USER_AGENT)
+            .http_status_as_error(false)
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+            .timeout_recv_body(Some(RESPONSE_TIMEOUT))
+            .timeout_send_body(Some(RESPONSE_TIMEOUT))
+            .middleware(BokioAuth { auth_header })
+            .build()
+            .into();
+
+        Ok(Self {
             base_url: base_url.to_string(),
             company_id: company_id.to_string(),
-            auth_header: format!("Bearer {}", token),
-        }
+            agent,
+            retry: RetryPolicy::default(),
+            accounts_cache: None,
+            accounts: Mutex::new(None),
+        })
+    }
+
+    /// Finds an entry with the same date and title that has not been reversed.
+    fn find_journal_entry(&self, entry: &CreateJournal) -> Result<Option<JournalEntry>, Error> {
+        let date = entry.date.parse::<NaiveDate>()
+            .map_err(|e| Error::Parse(format!("datum {}: {}", entry.date, e)))?;
+        let journal = self.list_journal(Some(date), Some(date))?;
+        Ok(journal
+            .into_iter()
+            .find(|e| e.title.eq_ignore_ascii_case(&entry.title) && e.reversed_by_journal_entry_id.is_none()))
     }
 
-    pub fn create_journal_entry(&self, entry: &CreateJournal) -> Result<JournalEntry, Error> {
+    fn _create_journal_entry(&self, entry: &CreateJournal) -> Result<JournalEntry, Error> {
         let url = format!(
             "{}/companies/{}/journal-entries",
             self.base_url, self.company_id
         );
 
-        ureq::post(&url)
-            .header(ACCEPT, APPLICATION_JSON)
-            .header(AUTHORIZATION, &self.auth_header)
-            .send_json(&entry)?
+        let res = self.agent.post(&url)
+            .send_json(entry);
+        Ok(check_status(res)?
             .body_mut()
-            .read_json::<JournalEntry>()
+            .read_json::<JournalEntry>()?)
     }
 
-    pub fn upload(
+    fn _reverse_journal_entry(&self, journal_entry_id: &str) -> Result<JournalEntry, Error> {
+        let url = format!(
+            "{}/companies/{}/journal-entries/{}/reverse",
+            self.base_url, self.company_id, journal_entry_id
+        );
+
+        let res = self.agent.post(&url)
+            .send_empty();
+        Ok(check_status(res)?
+            .body_mut()
+            .read_json::<JournalEntry>()?)
+    }
+
+    fn _get_journal_entry(&self, journal_entry_id: &str) -> Result<JournalEntry, Error> {
+        let url = format!(
+            "{}/companies/{}/journal-entries/{}",
+            self.base_url, self.company_id, journal_entry_id
+        );
+
+        self.retry.run(|| {
+            let res = self.agent.get(&url)
+                .call();
+            Ok(check_status(res)?
+                .body_mut()
+                .read_json::<JournalEntry>()?)
+        })
+    }
+
+    fn _upload(
         &self,
         filename: &str,
         content_type: &str,
@@ -112,12 +273,11 @@ impl Bokio {
         let url = format!("{}/companies/{}/uploads", self.base_url, self.company_id);
 
         let mut m = Multipart::new();
-        let file = std::fs::File::open(&filename).expect(&format!("Kunde inte öppna {}", filename));
-        let basename = std::path::Path::new(&filename)
+        let file = std::fs::File::open(filename).map_err(|e| Error::io(filename, e))?;
+        let basename = std::path::Path::new(filename)
             .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
+            .and_then(|name| name.to_str())
+            .unwrap_or(filename)
             .to_string();
         m.add_stream(
             "file",
@@ -127,17 +287,38 @@ impl Bokio {
         );
         m.add_text("journalEntryId", journal_entry_id);
 
-        let mut prepared = m.prepare().unwrap();
+        let mut prepared = m.prepare().map_err(|e| Error::io(filename, e.error))?;
         let mut vec: Vec<u8> = Vec::new();
-        std::io::copy(&mut prepared, &mut vec).unwrap();
+        std::io::copy(&mut prepared, &mut vec).map_err(|e| Error::io(filename, e))?;
         let boundary = prepared.boundary();
-        ureq::post(url)
+        let res = self.agent.post(url)
             .content_type(format!("multipart/form-data; boundary={}", boundary))
-            .header(ACCEPT, APPLICATION_JSON)
-            .header(AUTHORIZATION, &self.auth_header)
-            .send(&vec)?
+            .send(&vec);
+        Ok(check_status(res)?
+            .body_mut()
+            .read_json::<UploadResponse>()?)
+    }
+
+    fn _list_uploads(&self, journal_entry_id: &str, page: &PageReq) -> Result<UploadListing, Error> {
+        let url = format!("{}/companies/{}/uploads", self.base_url, self.company_id);
+
+        let res = self.agent.get(url)
+            .query("page", page.page.to_string())
+            .query("pageSize", page.size.to_string())
+            .query("query", format!("journalEntryId=={}", journal_entry_id))
+            .call();
+        Ok(check_status(res)?
+            .body_mut()
+            .read_json::<UploadListing>()?)
+    }
+
+    fn _chart_of_accounts(&self) -> Result<Vec<Account>, Error> {
+        let url = format!("{}/companies/{}/chart-of-accounts", self.base_url, self.company_id);
+        let res = self.agent.get(url)
+            .call();
+        Ok(check_status(res)?
             .body_mut()
-            .read_json::<UploadResponse>()
+            .read_json::<Vec<Account>>()?)
     }
 
     fn _list_journal_entries(&self, page: &PageReq) -> Result<JournalEntryListing, Error> {
@@ -146,15 +327,20 @@ impl Bokio {
             self.base_url, self.company_id, page.page, page.size
         );
 
-        ureq::get(url)
-            .header(ACCEPT, APPLICATION_JSON)
-            .header(AUTHORIZATION, &self.auth_header)
-            .call()?
+        let res = self.agent.get(url)
+            .call();
+        Ok(check_status(res)?
             .body_mut()
-            .read_json::<JournalEntryListing>()
+            .read_json::<JournalEntryListing>()?)
+    }
+}
+
+impl AccountingBackend for Bokio {
+    fn name(&self) -> String {
+        "Bokio".to_string()
     }
 
-    pub fn list_journal(
+    fn list_journal(
         &self,
         start_date: Option<NaiveDate>,
         end_date: Option<NaiveDate>,
@@ -164,7 +350,7 @@ impl Bokio {
         let mut reached_end = false;
         // Results are returned in descending order
         while !reached_end {
-            let lst = self._list_journal_entries(&page)?;
+            let lst = self.retry.run(|| self._list_journal_entries(&page))?;
             if lst.items.is_empty() {
                 break;
             }
@@ -172,7 +358,7 @@ impl Bokio {
             if let Some(start_date) = start_date {
                 if let Some(end_date) = end_date {
                     for entry in lst.items {
-                        let date = entry.date.parse::<NaiveDate>().unwrap();
+                        let date = entry.parse_date()?;
                         if date < start_date {
                             reached_end = true;
                             break;
@@ -182,7 +368,7 @@ impl Bokio {
                     }
                 } else {
                     for entry in lst.items {
-                        let date = entry.date.parse::<NaiveDate>().unwrap();
+                        let date = entry.parse_date()?;
                         if date < start_date {
                             reached_end = true;
                         } else if entry.title.starts_with("Kassa") {
@@ -192,7 +378,7 @@ impl Bokio {
                 }
             } else if let Some(end_date) = end_date {
                 for entry in lst.items {
-                    let date = entry.date.parse::<NaiveDate>().unwrap();
+                    let date = entry.parse_date()?;
                     if date <= end_date && entry.title.starts_with("Kassa") {
                         result.push(entry);
                     }
@@ -210,4 +396,94 @@ impl Bokio {
 
         Ok(result)
     }
+
+    fn get_journal_entry(&self, journal_entry_id: &str) -> Result<Option<JournalEntry>, Error> {
+        match self._get_journal_entry(journal_entry_id) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(Error::Http { status: 404, .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Creating a journal entry is not idempotent. A request that failed may still
+    /// have created the entry, so the journal is checked before trying again.
+    fn create_journal_entry(&self, entry: &NewJournalEntry) -> Result<JournalEntry, Error> {
+        let request = CreateJournal::from(entry);
+        let mut first = true;
+        self.retry.run(|| {
+            if !first && let Some(existing) = self.find_journal_entry(&request)? {
+                return Ok(existing);
+            }
+            first = false;
+            self._create_journal_entry(&request)
+        })
+    }
+
+    /// Reverses a journal entry and returns the reversing entry. Like creating an
+    /// entry this is not idempotent, so the entry is checked before trying again.
+    fn reverse_journal_entry(&self, journal_entry_id: &str) -> Result<JournalEntry, Error> {
+        let mut first = true;
+        self.retry.run(|| {
+            if !first
+                && let Some(reversed_by) = self._get_journal_entry(journal_entry_id)?.reversed_by_journal_entry_id
+            {
+                return self._get_journal_entry(&reversed_by);
+            }
+            first = false;
+            self._reverse_journal_entry(journal_entry_id)
+        })
+    }
+
+    fn upload(
+        &self,
+        filename: &str,
+        content_type: &str,
+        journal_entry_id: &str,
+    ) -> Result<UploadResponse, Error> {
+        self.retry.run(|| self._upload(filename, content_type, journal_entry_id))
+    }
+
+    /// The uploads attached to a journal entry.
+    fn list_uploads(&self, journal_entry_id: &str) -> Result<Vec<UploadResponse>, Error> {
+        let mut page = PageReq { page: 1, size: 100 };
+        let mut result: Vec<UploadResponse> = Vec::new();
+        loop {
+            let lst = self.retry.run(|| self._list_uploads(journal_entry_id, &page))?;
+            let last = lst.current_page >= lst.total_pages || lst.items.is_empty();
+            result.extend(lst.items);
+            if last {
+                break;
+            }
+            page.page += 1;
+        }
+        Ok(result)
+    }
+
+    /// The company's chart of accounts. It is fetched once per run, or read from
+    /// `accounts_cache` when saved within the last day. `refresh` fetches it from the
+    /// API unless that has already been done in this run.
+    fn chart_of_accounts(&self, refresh: bool) -> Result<Option<Vec<Account>>, Error> {
+        let mut accounts = self.accounts.lock().unwrap();
+        if let Some((accounts, fetched)) = accounts.as_ref()
+            && (!refresh || *fetched)
+        {
+            return Ok(Some(accounts.clone()));
+        }
+
+        let cached = self.accounts_cache.as_deref()
+            .filter(|_| !refresh)
+            .and_then(|path| AccountsCache::load(path, &self.company_id));
+        let (list, fetched) = match cached {
+            Some(cached) => (cached, false),
+            None => {
+                let list = self.retry.run(|| self._chart_of_accounts())?;
+                if let Some(path) = &self.accounts_cache {
+                    AccountsCache::save(path, &self.company_id, &list);
+                }
+                (list, true)
+            }
+        };
+        accounts.replace((list.clone(), fetched));
+        Ok(Some(list))
+    }
 }
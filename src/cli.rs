@@ -0,0 +1,237 @@
+use crate::bokio::BOKIO_API_URL;
+use crate::eskassa::DINKASSA_API_URL;
+use crate::utils::{tolka_konto, tolka_nummer};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Command line. Without a subcommand `import` is run, so that `esbokio --yes`
+/// works like before the subcommands were added.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Importerar Z-Rapporter från dinkassa.se (ES Kassa) till Bokio"
+)]
+pub struct Options {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub import: ImportOptions,
+    #[command(flatten)]
+    pub global: GlobalOptions,
+}
+
+impl Options {
+    /// Global options are accepted before and after the subcommand, but the import options
+    /// before another subcommand would be ignored.
+    pub fn check(self) -> Result<Self, clap::Error> {
+        if self.command.is_some() && self.import != ImportOptions::default() {
+            let message = "flaggor för import, som --yes, kan inte anges före ett annat kommando";
+            return Err(Options::command().error(ErrorKind::ArgumentConflict, message));
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Lista Z-Rapporter och om de är bokförda i Bokio
+    List,
+    /// Importera Z-Rapporter till Bokio (standard)
+    Import(ImportOptions),
+    /// Importera Z-Rapporter från SIE4-filer utan att hämta dem från dinkassa.se
+    ImportSie {
+        #[arg(required = true, value_name = "KATALOG|FIL",
+            help = "SIE4-filer, eller kataloger med filer som slutar på .si, .se eller .sie")]
+        paths: Vec<PathBuf>,
+        #[arg(long, short, help = "Importera alla Z-Rapporter som inte redan är bokförda utan att fråga")]
+        yes: bool,
+        #[arg(long, help = "Visa verifikaten som skulle skapas men bokför ingenting i Bokio")]
+        dry_run: bool,
+    },
+    /// Visa antal bokförda och ej bokförda Z-Rapporter per kassa
+    Status {
+        #[arg(long, value_name = "FIL", value_parser = parse_summary,
+            help = "Skriv sammanfattningen i JSON-format till FIL")]
+        summary: Option<PathBuf>,
+    },
+    /// Kontrollera att bokförda verifikat stämmer med Z-Rapporterna
+    Verify,
+    /// Vänd (makulera) verifikaten för bokförda Z-Rapporter
+    Reverse {
+        #[arg(required = true, value_name = "NR", value_parser = parse_report,
+            help = "Z-Rapporter att vända, nummer eller kassa:nummer (t.ex. 12 eller k1:12)")]
+        reports: Vec<String>,
+        #[arg(long, short, help = "Fråga inte innan verifikaten vänds")]
+        yes: bool,
+    },
+    /// Stäm av lokalt registrerade importer mot Bokio
+    Reconcile,
+    /// Slutför avbrutna importer, t.ex. ladda upp underlag som saknas
+    Resume,
+    /// Leta efter bokförda Z-Rapporter vars verifikat saknar underlag i Bokio
+    VerifyAttachments {
+        #[arg(long, short, help = "Ladda upp underlag som saknas utan att fråga")]
+        yes: bool,
+    },
+    /// Spara lösenord och token i nyckelringen efter att ha kontrollerat dem
+    Login,
+    /// Spara PDF, SIE4 och verifikat för Z-Rapporterna utan att bokföra
+    Export {
+        #[arg(long, short, value_name = "KATALOG", help = "Katalog att spara filerna i (standard är --output-dir)")]
+        output: Option<PathBuf>,
+    },
+    /// Spara verifikaten för Z-Rapporterna i en SIE4-fil, för import i Bokio eller annat program
+    ExportSie {
+        #[arg(long, short, value_name = "FIL",
+            help = "SIE4-fil att spara (standard är zrapporter-START-SLUT.se i --output-dir)")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Default, PartialEq, Args)]
+pub struct ImportOptions {
+    #[arg(long, short, conflicts_with_all = ["only", "none"],
+        help = "Importera alla Z-Rapporter som inte redan är bokförda utan att fråga")]
+    pub yes: bool,
+    #[arg(long, value_name = "NR", value_delimiter = ',', value_parser = parse_report, conflicts_with = "none",
+        help = "Importera bara angivna Z-Rapporter (t.ex. 12,13 eller k1:12) utan att fråga")]
+    pub only: Vec<String>,
+    #[arg(long, help = "Lista Z-Rapporterna men importera ingenting")]
+    pub none: bool,
+    #[arg(long, help = "Visa verifikaten som skulle skapas men bokför ingenting i Bokio")]
+    pub dry_run: bool,
+    #[arg(long, alias = "save", help = "Spara filer efter import")]
+    pub save_files: bool,
+    #[arg(long, value_name = "FIL", value_parser = parse_summary,
+        help = "Skriv en sammanfattning i JSON-format till FIL")]
+    pub summary: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Inställningar")]
+pub struct GlobalOptions {
+    #[arg(long, global = true, env = "ESBOKIO_CONFIG", value_name = "FIL",
+        help = "Konfigurationsfil med profiler (standard ~/.config/esbokio/config.toml)")]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true, env = "ESBOKIO_PROFILE", value_name = "NAMN",
+        help = "Profil i konfigurationsfilen")]
+    pub profile: Option<String>,
+
+    #[arg(long, global = true, env = "DINKASSA_API_URL", default_value = DINKASSA_API_URL, value_name = "URL",
+        help = "Adress till dinkassa.se API")]
+    pub dinkassa_api_url: String,
+    #[arg(long, global = true, env = "DINKASSA_USERNAME", value_name = "NAMN",
+        help = "Användarnamn för dinkassa.se")]
+    pub dinkassa_username: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_PASSWORD", hide_env_values = true, value_name = "LÖSENORD",
+        help = "Lösenord för dinkassa.se")]
+    pub dinkassa_password: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_PASSWORD_FILE", value_name = "FIL",
+        help = "Läs lösenordet från första raden i FIL")]
+    pub dinkassa_password_file: Option<PathBuf>,
+    #[arg(long, global = true, env = "DINKASSA_PASSWORD_COMMAND", value_name = "KOMMANDO",
+        help = "Läs lösenordet från första raden som KOMMANDO skriver ut")]
+    pub dinkassa_password_command: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_INTEGRATOR_ID", value_name = "ID",
+        help = "Integratörs-ID för maskininloggning")]
+    pub dinkassa_integrator_id: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_MACHINE_ID", value_name = "ID",
+        help = "Kassans ID för maskininloggning")]
+    pub dinkassa_machine_id: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_MACHINE_KEY", hide_env_values = true, value_name = "KEY",
+        help = "Kassans nyckel för maskininloggning")]
+    pub dinkassa_machine_key: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_MACHINE_KEY_FILE", value_name = "FIL",
+        help = "Läs kassans nyckel från första raden i FIL")]
+    pub dinkassa_machine_key_file: Option<PathBuf>,
+    #[arg(long, global = true, env = "DINKASSA_MACHINE_KEY_COMMAND", value_name = "KOMMANDO",
+        help = "Läs kassans nyckel från första raden som KOMMANDO skriver ut")]
+    pub dinkassa_machine_key_command: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_MACHINE", value_name = "NAMN|ID|all",
+        help = "Kassa att hämta Z-Rapporter från, \"all\" för alla kassor")]
+    pub machine: Option<String>,
+    #[arg(long, global = true, env = "DINKASSA_SESSION_CACHE", value_name = "FIL",
+        help = "Fil där inloggningen på dinkassa.se sparas mellan körningar")]
+    pub session_cache: Option<PathBuf>,
+    #[arg(long, global = true, help = "Spara inte inloggningen")]
+    pub no_session_cache: bool,
+
+    #[arg(long, global = true, env = "BOKIO_API_URL", default_value = BOKIO_API_URL, value_name = "URL",
+        help = "Adress till Bokio API")]
+    pub bokio_api_url: String,
+    #[arg(long, global = true, env = "BOKIO_API_TOKEN", hide_env_values = true, value_name = "TOKEN",
+        help = "Token för privat integration i Bokio")]
+    pub bokio_api_token: Option<String>,
+    #[arg(long, global = true, env = "BOKIO_API_TOKEN_FILE", value_name = "FIL",
+        help = "Läs token från första raden i FIL")]
+    pub bokio_api_token_file: Option<PathBuf>,
+    #[arg(long, global = true, env = "BOKIO_API_TOKEN_COMMAND", value_name = "KOMMANDO",
+        help = "Läs token från första raden som KOMMANDO skriver ut")]
+    pub bokio_api_token_command: Option<String>,
+    #[arg(long, global = true, env = "BOKIO_COMPANY_ID", value_name = "UUID",
+        help = "Företagets ID i Bokio")]
+    pub bokio_company_id: Option<String>,
+    #[arg(long, global = true, env = "ESBOKIO_LEDGER", value_name = "KATALOG",
+        help = "Bokför i filer i KATALOG i stället för i Bokio")]
+    pub ledger: Option<PathBuf>,
+    #[arg(long, global = true, env = "BOKIO_ACCOUNT_MAP", value_name = "FIL",
+        help = "Fil med regler för kontomappning")]
+    pub account_map: Option<PathBuf>,
+    #[arg(long, global = true, env = "BOKIO_ROUNDING_ACCOUNT", value_name = "KONTO", value_parser = parse_account,
+        help = "Konto för öresavrundning, t.ex. 3740")]
+    pub rounding_account: Option<i32>,
+    #[arg(long, global = true, help = "Bokför inte öresavrundning")]
+    pub no_rounding_account: bool,
+
+    #[arg(long, global = true, value_name = "YYYY-MM-DD", allow_hyphen_values = true,
+        conflicts_with_all = ["start", "end"], help = "Z-Rapporter för ett datum (standard är idag)")]
+    pub date: Option<String>,
+    #[arg(long, global = true, value_name = "YYYY-MM-DD", allow_hyphen_values = true,
+        help = "Startdatum för Z-Rapporter (standard är idag)")]
+    pub start: Option<String>,
+    #[arg(long, global = true, value_name = "YYYY-MM-DD", allow_hyphen_values = true,
+        help = "Slutdatum för Z-Rapporter (standard är startdatum)")]
+    pub end: Option<String>,
+    #[arg(long, global = true, env = "ESBOKIO_OUTPUT_DIR", value_name = "KATALOG",
+        help = "Katalog för sparade filer (standard är aktuell katalog)")]
+    pub output_dir: Option<PathBuf>,
+    #[arg(long, global = true, env = "ESBOKIO_STATE", value_name = "FIL",
+        help = "Fil med importerade Z-Rapporter (standard ~/.local/share/esbokio/state.json)")]
+    pub state: Option<PathBuf>,
+    #[arg(long, global = true, value_name = "N",
+        help = "Antal nya försök vid tillfälliga fel (standard 3)")]
+    pub retries: Option<u32>,
+}
+
+fn parse_report(s: &str) -> Result<String, String> {
+    let s = s.trim().to_lowercase();
+    if tolka_nummer(&s).is_none() {
+        return Err("ange nummer eller kassa:nummer, t.ex. 12 eller k1:12".to_string());
+    }
+    Ok(s)
+}
+
+/// The summary is not written to stdout, where it would be mixed with the listings and progress.
+fn parse_summary(s: &str) -> Result<PathBuf, String> {
+    if s == "-" {
+        return Err("ange en fil, sammanfattningen kan inte skrivas till standard output".to_string());
+    }
+    Ok(PathBuf::from(s))
+}
+
+fn parse_account(s: &str) -> Result<i32, String> {
+    tolka_konto(s).ok_or_else(|| "kontot måste vara ett fyrsiffrigt BAS-konto".to_string())
+}
+
+/// Removes empty DINKASSA_*, BOKIO_* and ESBOKIO_* variables, which clap would otherwise read
+/// as empty values. The example scripts set unused variables to ''.
+pub fn remove_empty_env_vars() {
+    for (key, value) in std::env::vars_os() {
+        let name = key.to_string_lossy();
+        if value.is_empty() && ["DINKASSA_", "BOKIO_", "ESBOKIO_"].iter().any(|p| name.starts_with(p)) {
+            // SAFETY: called at the start of main before any other threads are started
+            unsafe { std::env::remove_var(&key) };
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::error::Error;
+use crate::secret::Secret;
+use crate::utils::tolka_konto;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Settings per company, read from a TOML file:
+///
+/// ```toml
+/// default_profile = "butik"
+///
+/// [profile.butik]
+/// dinkassa_username = "butik@example.com"
+/// dinkassa_password = { env = "BUTIK_DINKASSA_PASSWORD" }
+/// machine = "Kassa 1"
+/// bokio_company_id = "company-1"
+/// bokio_api_token = { env = "BUTIK_BOKIO_API_TOKEN" }
+/// account_map = "butik-konton.toml"
+/// output_dir = "butik"
+/// lookback_days = 7
+/// ```
+///
+/// Values given on the command line or in environment variables take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub dinkassa_username: Option<String>,
+    pub dinkassa_password: Option<Secret>,
+    pub dinkassa_integrator_id: Option<String>,
+    pub dinkassa_machine_id: Option<String>,
+    pub dinkassa_machine_key: Option<Secret>,
+    pub machine: Option<String>,
+    pub bokio_company_id: Option<String>,
+    pub bokio_api_token: Option<Secret>,
+    /// Directory for a journal in files instead of Bokio, relative to the directory of
+    /// the config file.
+    pub ledger: Option<PathBuf>,
+    /// Relative to the directory of the config file.
+    pub account_map: Option<PathBuf>,
+    pub rounding_account: Option<String>,
+    /// Relative to the directory of the config file.
+    pub output_dir: Option<PathBuf>,
+    /// Days back from today to fetch Z-reports for when no dates are given.
+    pub lookback_days: Option<u64>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::io(path.display(), e))?;
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| Error::Parse(format!("konfiguration {}: {}", path.display(), e)))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for (name, profile) in config.profiles.iter_mut() {
+            if let Some(account) = &profile.rounding_account
+                && tolka_konto(account).is_none()
+            {
+                return Err(Error::Validation(format!(
+                    "Konfiguration {}, profil {}: ogiltigt kontonummer \"{}\"",
+                    path.display(), name, account
+                )));
+            }
+            profile.account_map = profile.account_map.take().map(|p| dir.join(p));
+            profile.output_dir = profile.output_dir.take().map(|p| dir.join(p));
+            profile.ledger = profile.ledger.take().map(|p| dir.join(p));
+            let secrets = [
+                &mut profile.dinkassa_password,
+                &mut profile.dinkassa_machine_key,
+                &mut profile.bokio_api_token,
+            ];
+            for secret in secrets.into_iter().flatten() {
+                if let Secret::File(p) = secret {
+                    *p = dir.join(&*p);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// The named profile, or the default profile when no name is given.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &Profile)>, Error> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        match self.profiles.get_key_value(name) {
+            Some((name, profile)) => Ok(Some((name, profile))),
+            None => {
+                let names: Vec<&str> = self.profiles.keys().map(|n| n.as_str()).collect();
+                Err(Error::Validation(format!(
+                    "Profilen {} finns inte, tillgängliga profiler: {}",
+                    name,
+                    if names.is_empty() { "inga".to_string() } else { names.join(", ") }
+                )))
+            }
+        }
+    }
+}
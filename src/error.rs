@@ -0,0 +1,171 @@
+use chrono::{DateTime, Utc};
+use http::header::RETRY_AFTER;
+use http::{Response, StatusCode};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use ureq::{Body, ResponseExt};
+
+/// Errors reported by esbokio. Each kind has its own exit code so that scripts can
+/// tell a rejected login from a network problem or bad data.
+#[derive(Debug)]
+pub enum Error {
+    /// Login rejected or credentials missing.
+    Auth(String),
+    /// The API answered with an error status.
+    Http {
+        status: u16,
+        url: String,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    /// Network failure, e.g. timeout or connection refused.
+    Transport(ureq::Error),
+    /// Unexpected data from an API or file.
+    Parse(String),
+    /// Input that cannot be booked, e.g. an invalid account number.
+    Validation(String),
+    /// Local file error.
+    Io(std::io::Error),
+}
+
+/// Exit code for [`Error::Validation`], also used when `verify` finds a differing entry.
+pub const EXIT_VALIDATION: i32 = 6;
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Auth(_) => 2,
+            Error::Http { .. } => 3,
+            Error::Transport(_) => 4,
+            Error::Parse(_) => 5,
+            Error::Validation(_) => EXIT_VALIDATION,
+            Error::Io(_) => 7,
+        }
+    }
+
+    /// Errors that may go away if the request is sent again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Http { status, .. } => matches!(status, 408 | 429 | 500 | 502 | 503 | 504),
+            Error::Transport(e) => matches!(
+                e,
+                ureq::Error::Timeout(_) | ureq::Error::Io(_) | ureq::Error::ConnectionFailed | ureq::Error::HostNotFound
+            ),
+            _ => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// An IO error that names the file involved.
+    pub fn io(path: impl Display, e: std::io::Error) -> Error {
+        Error::Io(std::io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
+
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Auth(msg) if msg.is_empty() => write!(f, "Behörighet saknas"),
+            Error::Auth(msg) => write!(f, "Behörighet saknas: {}", msg),
+            Error::Http { status, url, body, .. } => {
+                write!(f, "HTTP {}", status)?;
+                if !url.is_empty() {
+                    write!(f, " från {}", url)?;
+                }
+                if !body.is_empty() {
+                    write!(f, ": {}", body)?;
+                }
+                Ok(())
+            }
+            Error::Transport(e) => write!(f, "Nätverksfel: {}", e),
+            Error::Parse(msg) => write!(f, "Kunde inte tolka {}", msg),
+            Error::Validation(msg) => write!(f, "{}", msg),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for ureq::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Transport(e) => e,
+            e => ureq::Error::Other(Box::new(e)),
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::StatusCode(status) => Error::Http {
+                status,
+                url: String::new(),
+                body: String::new(),
+                retry_after: None,
+            },
+            ureq::Error::Json(e) => Error::Parse(format!("svar: {}", e)),
+            // Errors from our own middleware
+            ureq::Error::Other(e) => match e.downcast::<Error>() {
+                Ok(e) => *e,
+                Err(e) => Error::Transport(ureq::Error::Other(e)),
+            },
+            e => Error::Transport(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(format!("JSON: {}", e))
+    }
+}
+
+/// Turns 4xx and 5xx responses into [`Error::Http`] including the response body,
+/// or [`Error::Auth`] for 401 and 403. The agent must be configured with
+/// `http_status_as_error(false)`.
+pub fn check_status(res: Result<Response<Body>, ureq::Error>) -> Result<Response<Body>, Error> {
+    let mut res = res?;
+    let status = res.status();
+    if status.is_client_error() || status.is_server_error() {
+        let url = res.get_uri().to_string();
+        let retry_after = parse_retry_after(&res);
+        let body = res.body_mut().read_to_string().unwrap_or_default();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(Error::Auth(body.trim().to_string()));
+        }
+        return Err(Error::Http {
+            status: status.as_u16(),
+            url,
+            body: body.trim().to_string(),
+            retry_after,
+        });
+    }
+
+    Ok(res)
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(res: &Response<Body>) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
@@ -1,23 +1,29 @@
+use crate::error::{check_status, Error};
+use crate::retry::RetryPolicy;
 use crate::utils::{format_local_date, APPLICATION_JSON, DEFAULT_USER_AGENT};
-use chrono::NaiveDate;
-use http::header::{ACCEPT, USER_AGENT};
-use http::{HeaderValue, Request, Response};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta};
+use http::header::ACCEPT;
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
 use regex::Regex;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
+use std::io::Write;
 use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Mutex;
 use ureq::middleware::{Middleware, MiddlewareNext};
-use ureq::{Agent, Body, Error, SendBody};
+use ureq::typestate::WithoutBody;
+use ureq::{Agent, Body, RequestBuilder, SendBody};
 use urlencoding::encode;
 
 pub const DINKASSA_API_URL: &str = "https://www.dinkassa.se/api";
 
 const SESSION_ID: &str = "SessionId";
 const INTEGRATOR_ID: &str = "IntegratorId";
-//const MACHINE_ID: &str = "MachineId";
-//const MACHINE_KEY: &str = "MachineKey";
+const MACHINE_ID: &str = "MachineId";
+const MACHINE_KEY: &str = "MachineKey";
 
 const WEB_INTEGRATOR_ID: &str = "cc7c4035-ce21-40a6-95e2-a39a641a1c27";
 
@@ -55,14 +61,17 @@ impl DateRange {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DinKassa {
     base_url: String,
     agent: Agent,
     pub machine: Machine,
+    pub machines: Vec<Machine>,
+    pub retry: RetryPolicy,
 }
 
 #[derive(Deserialize)]
+#[allow(unused)]
 pub struct WebLoginResponse {
     #[serde(rename = "Id")]
     pub id: String, // sessionid header
@@ -75,6 +84,7 @@ pub struct WebLoginResponse {
 }
 
 #[derive(Deserialize)]
+#[allow(unused)]
 pub struct SettingsResponse {
     #[serde(rename = "Unit")]
     pub unit: String, // default unit
@@ -115,6 +125,7 @@ pub struct MachineResponse {
 
 #[derive(Clone, Debug)]
 #[derive(Deserialize)]
+#[allow(unused)]
 pub struct ZReportListItem {
     #[serde(rename = "Id")]
     pub id: String,
@@ -124,6 +135,15 @@ pub struct ZReportListItem {
     pub date_time: String,
     #[serde(rename = "CreatedBy")]
     pub created_by: String,
+    /// Not included in every listing.
+    #[serde(rename = "RegisterNumber", default)]
+    pub register: Option<u32>,
+}
+
+impl ZReportListItem {
+    pub fn datum(&self) -> String {
+        self.date_time.get(0..10).unwrap_or(&self.date_time).to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -135,6 +155,20 @@ pub struct ZReportListResponse {
     pub items: Vec<ZReportListItem>,
 }
 
+/// Identifies a Z-report across registers, e.g. "K1:42" reported on 2026-01-04.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ZReportKey {
+    pub register: u32,
+    pub number: u32,
+    pub date: String,
+}
+
+impl Display for ZReportKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "K{}:{} ({})", self.register, self.number, self.date)
+    }
+}
+
 //             "ZReport": "K1:1",
 //             "ReportDateTime": "2026-01-04T17:30:26",
 //             "FirstTransactionDateTime": "2026-01-04T11:10:12",
@@ -177,6 +211,13 @@ impl SIEReportListItem {
         }
     }
 
+    /// The report for a journal entry title from [`verifikatnamn`](Self::verifikatnamn),
+    /// e.g. "K1:12" for "Kassa 1, Z-Rapport #12".
+    pub fn zreport_from_verifikatnamn(title: &str) -> Option<String> {
+        let (register, number) = title.strip_prefix("Kassa ")?.split_once(", Z-Rapport #")?;
+        Some(format!("K{}:{}", register.parse::<u32>().ok()?, number.parse::<u32>().ok()?))
+    }
+
     pub fn number(&self) -> Result<u32, ParseIntError> {
         let re = Regex::new(r"K\d+:(\d+)").unwrap();
         if let Some(captures) = re.captures(&self.zreport) {
@@ -186,12 +227,26 @@ impl SIEReportListItem {
         }
     }
 
-    pub fn datum(&self) -> String {
-        self.report_date_time[0..10].to_string()
+    /// Register number from "K1:1", or 1 when the report has no register part.
+    pub fn register(&self) -> Result<u32, ParseIntError> {
+        let re = Regex::new(r"K(\d+):\d+").unwrap();
+        if let Some(captures) = re.captures(&self.zreport) {
+            u32::from_str(&captures[1])
+        } else {
+            Ok(1)
+        }
     }
 
-    pub fn konto(&self, nr: &str) -> Option<Decimal> {
-        self.accounts.iter().find_map(|a| if a.number == nr { Some(a.amount) } else { None })
+    pub fn key(&self) -> Result<ZReportKey, ParseIntError> {
+        Ok(ZReportKey {
+            register: self.register()?,
+            number: self.number()?,
+            date: self.datum(),
+        })
+    }
+
+    pub fn datum(&self) -> String {
+        self.report_date_time.get(0..10).unwrap_or(&self.report_date_time).to_string()
     }
 }
 
@@ -216,80 +271,323 @@ fn end_of_day(date: &NaiveDate) -> String {
     date.format("%Y-%m-%dT23:59:59").to_string()
 }
 
-struct WebUserSession {
+/// Web user session persisted between runs, see [`DinKassa::login_username_password`].
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct SessionCache {
+    #[serde(default)]
+    base_url: String,
+    username: String,
     integrator_id: String,
     session_id: String,
+    expires_date_time: String,
+    cookies: String,
+}
+
+impl SessionCache {
+    fn load(path: &Path) -> Option<SessionCache> {
+        let json = std::fs::read(path).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn save(&self, path: &Path) {
+        let json = serde_json::to_vec_pretty(self).unwrap();
+        write_private_file(path, &json)
+            .inspect_err(|e| eprintln!("Kunde inte spara session i {}: {}", path.display(), e))
+            .ok();
+    }
+
+    /// The session is considered valid until a few minutes before it expires. A session
+    /// without a known expiry time is assumed to be valid until the API says otherwise.
+    fn is_valid(&self) -> bool {
+        if self.session_id.is_empty() {
+            return false;
+        }
+
+        match parse_date_time(&self.expires_date_time) {
+            Some(expires) => chrono::Local::now() + TimeDelta::minutes(5) < expires,
+            None => true,
+        }
+    }
+}
+
+fn parse_date_time(s: &str) -> Option<DateTime<chrono::Local>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&chrono::Local));
+    }
+
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .and_then(|dt| dt.and_local_timezone(chrono::Local).earliest())
+}
+
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // mode() only applies to new files
+        if std::fs::exists(path)? {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents)
+}
+
+fn save_cookies(agent: &Agent) -> Result<String, Error> {
+    let jar = agent.cookie_jar_lock();
+    let mut buf = Vec::new();
+    jar.save_json(&mut buf)?;
+    jar.release();
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+fn load_cookies(agent: &Agent, cookies: &str) -> Result<(), Error> {
+    let mut jar = agent.cookie_jar_lock();
+    jar.load_json(cookies.as_bytes())?;
+    jar.release();
+    Ok(())
+}
+
+fn plain_agent() -> Agent {
+    Agent::config_builder()
+        .accept(APPLICATION_JSON)
+        .user_agent(DEFAULT_USER_AGENT)
+        .http_status_as_error(false)
+        .build()
+        .into()
+}
+
+/// Adds the web session to each request. The session is renewed when it is about to
+/// expire or when the API answers 401, in which case the request is sent again.
+struct WebUserSession {
+    base_url: String,
+    /// Empty when logged in with a saved session, which then cannot be renewed.
+    password: String,
+    cache_path: Option<PathBuf>,
+    /// Agent without this middleware. All requests are sent with it, so that the
+    /// cookies of a renewed session are used by every later request.
+    agent: Agent,
+    session: Mutex<SessionCache>,
+}
+
+impl WebUserSession {
+    fn authenticate(&self) -> Result<SessionCache, Error> {
+        if self.password.is_empty() {
+            return Err(Error::Auth("Sessionen på dinkassa.se har gått ut och lösenord saknas".to_string()));
+        }
+        let mut session = self.session.lock().unwrap();
+        let res = authenticate(
+            &self.agent,
+            &self.base_url,
+            &session.integrator_id,
+            &session.username,
+            &self.password,
+        )?;
+        session.session_id = res.id;
+        session.expires_date_time = res.expires_date_time;
+        session.cookies = save_cookies(&self.agent)?;
+        if let Some(path) = &self.cache_path {
+            session.save(path);
+        }
+        Ok(session.clone())
+    }
+
+    fn add_headers(session: &SessionCache, headers: &mut HeaderMap) -> Result<(), ureq::Error> {
+        headers.insert(INTEGRATOR_ID, header_value(&session.integrator_id)?);
+        if !session.session_id.is_empty() {
+            headers.insert(SESSION_ID, header_value(&session.session_id)?);
+        }
+        Ok(())
+    }
 }
 
 impl Middleware for WebUserSession {
-    fn handle(&self, mut req: Request<SendBody>, next: MiddlewareNext)
+    fn handle(&self, mut req: Request<SendBody>, _next: MiddlewareNext)
               -> Result<Response<Body>, ureq::Error> {
 
-        req.headers_mut()
-            .insert(INTEGRATOR_ID, HeaderValue::from_str(&self.integrator_id).unwrap());
-        if !self.session_id.is_empty() {
-            req.headers_mut()
-                .insert(SESSION_ID, HeaderValue::from_str(&self.session_id).unwrap());
+        let session = self.session.lock().unwrap().clone();
+        let session = if session.is_valid() { session } else { self.authenticate()? };
+        Self::add_headers(&session, req.headers_mut())?;
+
+        // Only requests without a body can be sent again
+        if req.method() != Method::GET {
+            return self.agent.run(req);
         }
 
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+        let headers = req.headers().clone();
+
+        match self.agent.run(req) {
+            Ok(res) if res.status() == StatusCode::UNAUTHORIZED => {
+                let session = self.authenticate()?;
+                let mut retry = Request::builder().method(method).uri(uri).body(())?;
+                *retry.headers_mut() = headers;
+                Self::add_headers(&session, retry.headers_mut())?;
+                self.agent.run(retry)
+            }
+            res => res,
+        }
+    }
+}
+
+fn authenticate(
+    agent: &Agent,
+    base_url: &str,
+    integrator_id: &str,
+    username: &str,
+    password: &str,
+) -> Result<WebLoginResponse, Error> {
+    let url = format!("{}/session/Authenticate?type=2", base_url);
+    let mut body = std::collections::HashMap::new();
+    body.insert("Username", username);
+    body.insert("Password", password);
+
+    let res = agent.post(url)
+        .header(INTEGRATOR_ID, integrator_id)
+        .send_json(&body);
+    Ok(check_status(res)?
+        .body_mut()
+        .read_json::<WebLoginResponse>()?)
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, ureq::Error> {
+    Ok(HeaderValue::from_str(value).map_err(http::Error::from)?)
+}
+
+fn call(req: RequestBuilder<WithoutBody>) -> Result<Response<Body>, Error> {
+    check_status(req.call())
+}
+
+struct MachineKeySession {
+    integrator_id: String,
+    machine_id: String,
+    machine_key: String,
+}
+
+impl Middleware for MachineKeySession {
+    fn handle(&self, mut req: Request<SendBody>, next: MiddlewareNext)
+              -> Result<Response<Body>, ureq::Error> {
+
+        let headers = req.headers_mut();
+        headers.insert(INTEGRATOR_ID, header_value(&self.integrator_id)?);
+        headers.insert(MACHINE_ID, header_value(&self.machine_id)?);
+        headers.insert(MACHINE_KEY, header_value(&self.machine_key)?);
+
         // continue the middleware chain
         next.handle(req)
     }
 }
 
-
 impl DinKassa {
+    /// Whether a saved web user session can be used instead of logging in, so that
+    /// the password is not needed.
+    pub fn has_saved_session(base_url: &str, username: &str, cache_path: Option<&Path>) -> bool {
+        Self::saved_session(base_url, username, cache_path).is_some()
+    }
+
+    fn saved_session(base_url: &str, username: &str, cache_path: Option<&Path>) -> Option<SessionCache> {
+        cache_path
+            .and_then(SessionCache::load)
+            .filter(|c| c.base_url == base_url && c.username == username && c.integrator_id == WEB_INTEGRATOR_ID)
+            .filter(SessionCache::is_valid)
+    }
+
+    /// Log in as a web user. When `cache_path` is given the session and its cookies are
+    /// saved there and reused by later runs until the session expires. The password may
+    /// be empty when a saved session is used.
     pub fn login_username_password(
+        base_url: &str,
         username: &str,
         password: &str,
+        cache_path: Option<&Path>,
     ) -> Result<DinKassa, Error> {
-        let base_url = DINKASSA_API_URL;
         let integrator_id = WEB_INTEGRATOR_ID.to_string();
-        let url = format!("{}/session/Authenticate?type=2", base_url);
-        let mut body = std::collections::HashMap::new();
-        body.insert("Username", username);
-        body.insert("Password", password);
+
+        let web_agent = plain_agent();
+        let session = match Self::saved_session(base_url, username, cache_path) {
+            Some(session) => {
+                load_cookies(&web_agent, &session.cookies)?;
+                session
+            }
+            None => {
+                let res = authenticate(&web_agent, base_url, &integrator_id, username, password)?;
+                let session = SessionCache {
+                    base_url: base_url.to_string(),
+                    username: username.to_string(),
+                    integrator_id,
+                    session_id: res.id,
+                    expires_date_time: res.expires_date_time,
+                    cookies: save_cookies(&web_agent)?,
+                };
+                if let Some(path) = cache_path {
+                    session.save(path);
+                }
+                session
+            }
+        };
+
+        let session = WebUserSession {
+            base_url: base_url.to_string(),
+            password: password.to_string(),
+            cache_path: cache_path.map(Path::to_path_buf),
+            agent: web_agent,
+            session: Mutex::new(session),
+        };
 
         let agent: Agent = Agent::config_builder()
             .accept(APPLICATION_JSON)
             .user_agent(DEFAULT_USER_AGENT)
+            .http_status_as_error(false)
+            .middleware(session)
             .build()
             .into();
-        let res = agent.post(url)
-            .header(INTEGRATOR_ID, &integrator_id)
-            .send_json(&body)?
+
+        let settings = call(agent.get(format!("{}/settings", base_url)))?
             .body_mut()
-            .read_json::<WebLoginResponse>()?;
-        let session = WebUserSession {
-            integrator_id,
-            session_id: res.id
+            .read_json::<SettingsResponse>()?;
+
+        Self::connect(agent, base_url, settings.machine_id)
+    }
+
+    /// Authenticate as a machine (cash register) using the integrator id and the
+    /// machine id/key pair. No session is created, the credentials are sent with
+    /// every request.
+    pub fn login_machine_key(
+        base_url: &str,
+        integrator_id: &str,
+        machine_id: &str,
+        machine_key: &str,
+    ) -> Result<DinKassa, Error> {
+        let session = MachineKeySession {
+            integrator_id: integrator_id.to_string(),
+            machine_id: machine_id.to_string(),
+            machine_key: machine_key.to_string(),
         };
-        let jar = agent.cookie_jar_lock();
-        let mut buf = Vec::new();
-        jar.save_json(&mut buf)?;
-        jar.release();
 
         let agent: Agent = Agent::config_builder()
             .accept(APPLICATION_JSON)
-            .user_agent(USER_AGENT)
+            .user_agent(DEFAULT_USER_AGENT)
+            .http_status_as_error(false)
             .middleware(session)
             .build()
             .into();
-        let mut jar = agent.cookie_jar_lock();
-        jar.load_json(&buf[..])?;
-        jar.release();
 
-        let settings = agent.get(format!("{}/settings", base_url))
-            .call()?
-            .body_mut()
-            .read_json::<SettingsResponse>()?;
+        Self::connect(agent, base_url, machine_id.to_string())
+    }
 
-        let machine_id = settings.machine_id;
-        let machine = agent.get(format!("{}/machine", base_url))
-            .call()?
+    fn connect(agent: Agent, base_url: &str, machine_id: String) -> Result<DinKassa, Error> {
+        let machines = call(agent.get(format!("{}/machine", base_url)))?
             .body_mut()
             .read_json::<MachineResponse>()?
-            .items
+            .items;
+        let machine = machines
             .iter().find_map(|m| if m.id == machine_id { Some(m.clone()) } else { None })
             .unwrap_or(Machine {
                 customer_name: "".to_string(),
@@ -297,24 +595,40 @@ impl DinKassa {
                 name: "kassa".to_string()
             });
 
-
         Ok(DinKassa {
             agent,
             base_url: base_url.to_string(),
             machine,
+            machines,
+            retry: RetryPolicy::default(),
         })
     }
 
+    /// Find a machine by id or (case-insensitive) name.
+    pub fn find_machine(&self, id_or_name: &str) -> Option<&Machine> {
+        self.machines.iter().find(|m| m.id.eq_ignore_ascii_case(id_or_name))
+            .or_else(|| self.machines.iter().find(|m| m.name.eq_ignore_ascii_case(id_or_name)))
+    }
+
+    /// Returns a copy of this session working against another machine.
+    pub fn with_machine(&self, machine: &Machine) -> DinKassa {
+        DinKassa {
+            machine: machine.clone(),
+            ..self.clone()
+        }
+    }
+
     pub fn list_zreports(&self, dates: &DateRange) -> Result<ZReportListResponse, Error> {
         let url = format!("{}/reports/get-z-reports?machineId={}&startDateTime={}&endDateTime={}",
                           self.base_url, self.machine.id,
                           encode(&start_of_day(&dates.start_date)),
                           encode(&end_of_day(&dates.end_date)));
 
-        self.agent.get(url)
-            .call()?
-            .body_mut()
-            .read_json::<ZReportListResponse>()
+        self.retry.run(|| {
+            Ok(call(self.agent.get(&url))?
+                .body_mut()
+                .read_json::<ZReportListResponse>()?)
+        })
     }
 
     pub fn list_sie_reports(&self, dates: &DateRange) -> Result<SIEReportListResponse, Error> {
@@ -323,43 +637,49 @@ impl DinKassa {
                           format_local_date(&dates.start_date),
                           format_local_date(&dates.end_date));
 
-        self.agent.get(url)
-            .call()?
-            .body_mut()
-            .read_json::<SIEReportListResponse>()
+        self.retry.run(|| {
+            Ok(call(self.agent.get(&url))?
+                .body_mut()
+                .read_json::<SIEReportListResponse>()?)
+        })
     }
 
     pub fn zreport_pdf(&self, report_id: &str) -> Result<Vec<u8>, Error> {
         let url = format!("{}/reports/download-z-report/{}/{}",
             self.base_url, self.machine.id, report_id);
 
-        self.agent.get(url)
-            .header(ACCEPT, "application/pdf")
-            //.header(ACCEPT, "application/json")
-            //.header(ACCEPT, "text/plain")
-            //.header(ACCEPT, "*/*")
-            .call()?
-            .body_mut()
-            .read_to_vec()
+        self.retry.run(|| {
+            let req = self.agent.get(&url)
+                .header(ACCEPT, "application/pdf");
+                //.header(ACCEPT, "application/json")
+                //.header(ACCEPT, "text/plain")
+                //.header(ACCEPT, "*/*")
+            Ok(call(req)?
+                .body_mut()
+                .read_to_vec()?)
+        })
     }
 
     pub fn zreport_sie(&self, report_id: &str) -> Result<Vec<u8>, Error> {
         let url = format!("{}/reports/download-z-report/{}/{}/sie4",
                           self.base_url, self.machine.id, report_id);
 
-        self.agent.get(url)
-            .header(ACCEPT, "text/plain")
-            .header(ACCEPT, "*/*")
-            .call()?
-            .body_mut()
-            .read_to_vec()
+        self.retry.run(|| {
+            let req = self.agent.get(&url)
+                .header(ACCEPT, "text/plain")
+                .header(ACCEPT, "*/*");
+            Ok(call(req)?
+                .body_mut()
+                .read_to_vec()?)
+        })
     }
 
-    pub fn zreport_basename(&self, report: &ZReportListItem) -> String {
+    /// File name without extension for the files of Z-report `number` from this machine.
+    pub fn zreport_basename(&self, number: u32) -> String {
         if self.machine.customer_name.is_empty() {
-            format!("Z{}_{}", report.number, self.machine.name)
+            format!("Z{}_{}", number, self.machine.name)
         } else {
-            format!("Z{}_{}_{}", report.number, self.machine.name, self.machine.customer_name)
+            format!("Z{}_{}_{}", number, self.machine.name, self.machine.customer_name)
         }
     }
 
@@ -0,0 +1,235 @@
+use crate::backend::{AccountingBackend, NewJournalEntry};
+use crate::bokio::{Account, JournalEntry, JournalEntryAccount, UploadResponse};
+use crate::error::Error;
+use crate::sie::{self, Sie};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "journal.json";
+const SIE4_FILE: &str = "journal.se";
+const UPLOADS_DIR: &str = "underlag";
+/// Voucher series, the entries are numbered V1, V2, ...
+const SERIES: &str = "V";
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Journal {
+    #[serde(default)]
+    entries: Vec<JournalEntry>,
+    #[serde(default)]
+    uploads: Vec<UploadResponse>,
+    /// The id of the last entry created, ids of removed entries are not used again.
+    #[serde(default)]
+    last_id: u64,
+    /// For #FNAMN in the SIE4 file.
+    #[serde(default)]
+    company_name: String,
+    /// Account names for #KONTO in the SIE4 file.
+    #[serde(default)]
+    accounts: BTreeMap<i32, String>,
+}
+
+/// A journal kept in files in a directory, for companies that book the Z-reports in
+/// another accounting system than Bokio. The entries are saved in `journal.json` and
+/// attached files are copied to `underlag/`. After each change all entries are also
+/// written to `journal.se`, to be imported as SIE4 in the accounting system.
+pub struct Ledger {
+    dir: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf() }
+    }
+
+    fn load(&self) -> Result<Journal, Error> {
+        let path = self.dir.join(JOURNAL_FILE);
+        match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Journal::default()),
+            Err(e) => Err(Error::io(path.display(), e)),
+        }
+    }
+
+    fn save(&self, journal: &Journal) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| Error::io(self.dir.display(), e))?;
+        let path = self.dir.join(JOURNAL_FILE);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec_pretty(journal)?;
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|e| Error::io(path.display(), e))?;
+
+        let path = self.dir.join(SIE4_FILE);
+        std::fs::write(&path, sie::write(&sie4(journal)?)).map_err(|e| Error::io(path.display(), e))
+    }
+
+    fn new_entry(journal: &mut Journal, title: &str, date: &str, items: Vec<JournalEntryAccount>) -> JournalEntry {
+        // Journals saved before the counter was kept only have the ids of the entries
+        let max_id = journal.entries.iter().filter_map(|e| e.id.parse::<u64>().ok()).max().unwrap_or(0);
+        journal.last_id = journal.last_id.max(max_id) + 1;
+        let id = journal.last_id.to_string();
+        JournalEntry {
+            journal_entry_number: format!("{}{}", SERIES, id),
+            id,
+            title: title.to_string(),
+            date: date.to_string(),
+            items,
+            reversing_journal_entry_id: None,
+            reversed_by_journal_entry_id: None,
+        }
+    }
+}
+
+/// All entries as SIE4 vouchers in series V, numbered as in the journal.
+fn sie4(journal: &Journal) -> Result<Sie, Error> {
+    let mut sie4 = Sie {
+        program: Some("esbokio".to_string()),
+        program_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        sie_type: Some(4),
+        generated: Some(chrono::Local::now().date_naive()),
+        company_name: Some(journal.company_name.clone()),
+        ..Sie::default()
+    };
+    let konton: BTreeSet<i32> = journal.entries.iter().flat_map(|e| e.items.iter().map(|i| i.account)).collect();
+    for konto in konton {
+        sie4.accounts.push(sie::Account {
+            number: konto.to_string(),
+            name: journal.accounts.get(&konto).cloned().unwrap_or_default(),
+        });
+    }
+    for entry in journal.entries.iter() {
+        sie4.vouchers.push(sie::Voucher {
+            series: SERIES.to_string(),
+            number: entry.id.clone(),
+            date: entry.parse_date()?,
+            text: entry.title.clone(),
+            transactions: entry
+                .items
+                .iter()
+                .map(|item| sie::Transaction {
+                    account: item.account.to_string(),
+                    objects: Vec::new(),
+                    amount: item.debit - item.credit,
+                })
+                .collect(),
+        });
+    }
+    Ok(sie4)
+}
+
+impl AccountingBackend for Ledger {
+    fn name(&self) -> String {
+        format!("bokföringen i {}", self.dir.display())
+    }
+
+    fn list_journal(&self, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>)
+                    -> Result<Vec<JournalEntry>, Error> {
+        let mut result = Vec::new();
+        // Newest first, like Bokio
+        for entry in self.load()?.entries.into_iter().rev() {
+            let date = entry.parse_date()?;
+            if entry.title.starts_with("Kassa")
+                && start_date.is_none_or(|start| date >= start)
+                && end_date.is_none_or(|end| date <= end)
+            {
+                result.push((date, entry));
+            }
+        }
+        result.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+        Ok(result.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    fn get_journal_entry(&self, journal_entry_id: &str) -> Result<Option<JournalEntry>, Error> {
+        Ok(self.load()?.entries.into_iter().find(|e| e.id == journal_entry_id))
+    }
+
+    fn create_journal_entry(&self, entry: &NewJournalEntry) -> Result<JournalEntry, Error> {
+        let mut journal = self.load()?;
+        let items = entry
+            .items
+            .iter()
+            .zip(1..)
+            .map(|(item, id)| JournalEntryAccount {
+                id,
+                account: item.account,
+                debit: item.debit,
+                credit: item.credit,
+            })
+            .collect();
+        let created = Self::new_entry(&mut journal, &entry.title, &entry.date, items);
+        created.parse_date()?;
+        if !entry.company_name.is_empty() {
+            journal.company_name = entry.company_name.clone();
+        }
+        for item in entry.items.iter().filter(|item| !item.name.is_empty()) {
+            journal.accounts.insert(item.account, item.name.clone());
+        }
+        journal.entries.push(created.clone());
+        self.save(&journal)?;
+        Ok(created)
+    }
+
+    fn reverse_journal_entry(&self, journal_entry_id: &str) -> Result<JournalEntry, Error> {
+        let mut journal = self.load()?;
+        let Some(i) = journal.entries.iter().position(|e| e.id == journal_entry_id) else {
+            return Err(Error::Validation(format!("Verifikat {} finns inte", journal_entry_id)));
+        };
+        let original = journal.entries[i].clone();
+        if original.reversed_by_journal_entry_id.is_some() {
+            return Err(Error::Validation(format!("Verifikat {} är redan vänt", original.journal_entry_number)));
+        }
+
+        let items = original
+            .items
+            .iter()
+            .map(|item| JournalEntryAccount { debit: item.credit, credit: item.debit, ..*item })
+            .collect();
+        let title = format!("Vändning av {}", original.journal_entry_number);
+        let mut reversing = Self::new_entry(&mut journal, &title, &original.date, items);
+        reversing.reversing_journal_entry_id = Some(original.id.clone());
+        journal.entries[i].reversed_by_journal_entry_id = Some(reversing.id.clone());
+        journal.entries.push(reversing.clone());
+        self.save(&journal)?;
+        Ok(reversing)
+    }
+
+    fn upload(&self, filename: &str, content_type: &str, journal_entry_id: &str) -> Result<UploadResponse, Error> {
+        let mut journal = self.load()?;
+        let Some(entry) = journal.entries.iter().find(|e| e.id == journal_entry_id) else {
+            return Err(Error::Validation(format!("Verifikat {} finns inte", journal_entry_id)));
+        };
+        let basename = Path::new(filename)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(filename)
+            .to_string();
+        let dir = self.dir.join(UPLOADS_DIR);
+        let target = dir.join(format!("{}-{}", entry.journal_entry_number, basename));
+        std::fs::create_dir_all(&dir)
+            .and_then(|_| std::fs::copy(filename, &target))
+            .map_err(|e| Error::io(filename, e))?;
+
+        let upload = UploadResponse {
+            id: (journal.uploads.len() + 1).to_string(),
+            description: basename,
+            content_type: content_type.to_string(),
+            journal_entry_id: journal_entry_id.to_string(),
+        };
+        journal.uploads.push(upload.clone());
+        self.save(&journal)?;
+        Ok(upload)
+    }
+
+    fn list_uploads(&self, journal_entry_id: &str) -> Result<Vec<UploadResponse>, Error> {
+        Ok(self.load()?.uploads.into_iter().filter(|u| u.journal_entry_id == journal_entry_id).collect())
+    }
+
+    /// There is no chart of accounts, the accounts are checked when the SIE4 file is imported.
+    fn chart_of_accounts(&self, _refresh: bool) -> Result<Option<Vec<Account>>, Error> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,15 @@
+//! The modules of esbokio, shared by the binary in `main.rs` and the tests.
+
+pub mod backend;
+pub mod bokio;
+pub mod cli;
+pub mod config;
+pub mod error;
+pub mod eskassa;
+pub mod ledger;
+pub mod mapping;
+pub mod retry;
+pub mod secret;
+pub mod sie;
+pub mod state;
+pub mod utils;
@@ -1,102 +1,220 @@
-use crate::bokio::{Bokio, CreateJournal, CreateJournalAccount, JournalEntry, BOKIO_API_URL};
-use crate::eskassa::{DateRange, DinKassa, SIEReportListItem, ZReportListItem};
-use crate::utils::{format_local_date, money};
+use esbokio::backend::{AccountingBackend, NewJournalEntry, NewJournalLine};
+use esbokio::bokio::{Account, Bokio, CreateJournal, JournalEntry};
+use esbokio::cli::{self, Command, GlobalOptions, Options};
+use esbokio::config::{Config, Profile};
+use esbokio::sie::{self, Sie};
+use esbokio::secret::{Secret, keyring_lookup, keyring_name, keyring_store};
+use esbokio::state::{ImportRecord, ImportStatus, State, Step, content_hash};
+use esbokio::eskassa::{DateRange, DinKassa, Machine, SIEReportListItem, ZReportKey, ZReportListItem};
+use esbokio::error::{EXIT_VALIDATION, Error};
+use esbokio::ledger::Ledger;
+use esbokio::mapping::{AccountMap, MappedAccount};
+use esbokio::utils::{format_local_date, money};
 use chrono::naive::NaiveDate;
 use chrono::Days;
+use clap::Parser;
 use rust_decimal::{dec, Decimal};
-use std::collections::HashMap;
-use std::io::Write;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IsTerminal, Write};
 use std::iter::{once, repeat_n};
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
 use tabled::{builder::Builder, settings::Alignment, settings::Padding, settings::Style};
-use ureq::Error;
-use utils::{read_password_trim, read_prompt_trim, to_date};
+use esbokio::utils::{self, read_password_trim, read_prompt_trim, to_date, tolka_konto, tolka_nummer};
 
-mod bokio;
-mod eskassa;
-mod utils;
+/// How the reports to import are chosen.
+#[derive(Clone, Debug, PartialEq)]
+enum Urval {
+    /// Ask in a menu.
+    Fraga,
+    /// All reports not yet imported (--yes).
+    Alla,
+    /// Reports given as "42" or "k1:42" (--only).
+    Valda(Vec<String>),
+    /// List only (--none).
+    Ingen,
+}
 
 struct Cli {
+    dinkassa_api_url: String,
     dinkassa_username: String,
     dinkassa_password: String,
+    dinkassa_integrator_id: String,
+    dinkassa_machine_id: String,
+    dinkassa_machine_key: String,
+    machine: String,
+    session_cache: Option<PathBuf>,
     bokio_api_url: String,
     bokio_api_token: String,
+    /// Where the secrets not given as values are read from, by variable name, `None`
+    /// for the keyring. Read by `las_hemlighet` only when the login needs them.
+    hemliga_kallor: HashMap<&'static str, Option<Secret>>,
     bokio_company_id: String,
+    /// Directory of a journal in files, used instead of Bokio when set.
+    ledger: Option<PathBuf>,
     start_date: Option<NaiveDate>,
     end_date: Option<NaiveDate>,
     save_files: bool,
+    dry_run: bool,
+    /// Directory for saved files, the current directory when `None`.
+    output_dir: Option<PathBuf>,
+    avrundningskonto: Option<i32>,
+    kontomappning: AccountMap,
+    retries: Option<u32>,
+    urval: Urval,
+    summary: Option<PathBuf>,
+    /// Menus and prompts may be shown.
+    interaktiv: bool,
+    /// Profile in the config file.
+    profil: Option<String>,
+    /// Imports recorded locally.
+    tillstand: RefCell<State>,
 }
 
-fn check_arg(name: &str, arg: &str, iter: &mut impl Iterator<Item = String>) -> Option<String> {
-    let prefix = "--".to_string() + name;
-    if arg == prefix {
-        let val = iter.next();
-        return Some(val.expect(&format!("{} expected value", prefix)));
-    }
-
-    let prefix = prefix + "=";
-    if let Some(val) = arg.strip_prefix(&prefix) {
-        let val = Some(val)
-            .filter(|s| !s.is_empty())
-            .expect(&format!("{} expected value", prefix));
-        return Some(val.to_string());
-    }
-
-    None
+/// Prints the error and exits with the exit code for the kind of error.
+fn avsluta(msg: &str, err: Error) -> ! {
+    eprintln!("{}: {}", msg, err);
+    std::process::exit(err.exit_code());
 }
 
 struct RapportImport {
+    key: ZReportKey,
     sie: SIEReportListItem,
+    /// The accounts in `sie` after account mapping.
+    konton: Vec<MappedAccount>,
     report: ZReportListItem,
     verifikat: Option<JournalEntry>,
+    /// Imported in this run.
+    importerad: bool,
+    /// Previewed with --dry-run.
+    forhandsgranskad: bool,
+    fel: Option<Error>,
 }
 
 fn hamta_rapporter(
     args: &Cli,
     dinkassa: &DinKassa,
-    bokio: &Bokio,
+    bokforing: &dyn AccountingBackend,
 ) -> Result<(Vec<RapportImport>, DateRange), Error> {
-    let interval = DateRange::new(&args.start_date, &args.end_date);
+    let (mut importer, interval) = hamta_zrapporter(args, dinkassa)?;
     let bokio_start_date = interval.start_date.checked_sub_days(Days::new(14)).unwrap();
     /*
     if bokio_start_date.year() < interval.start_date.year() {
         bokio_start_date = NaiveDate::from_ymd_opt(interval.start_date.year(), 1, 1).unwrap();
     }
    */
-    let journal = bokio.list_journal(Some(bokio_start_date), Some(interval.end_date))?;
+    let journal = bokforing.list_journal(Some(bokio_start_date), Some(interval.end_date))?;
+    for import in importer.iter_mut() {
+        let title = import.sie.verifikatnamn().to_lowercase();
+        import.verifikat = journal
+            .iter()
+            .find(|e| e.title.to_lowercase() == title && e.reversed_by_journal_entry_id.is_none())
+            .cloned();
+        if let Some(post) = args.tillstand.borrow().find(&args.bokforing_id(), &import.report.id) {
+            if post.hash != content_hash(&import.sie) {
+                eprintln!(
+                    "Varning: Z-Rapport {} har ändrats i dinkassa.se sedan den bokfördes som {}",
+                    import.key, post.journal_entry_number
+                );
+            }
+            if import.verifikat.is_none() && !post.journal_entry_id.is_empty() {
+                import.verifikat = hamta_registrerat_verifikat(bokforing, post)?;
+            }
+        }
+    }
+
+    Ok((importer, interval))
+}
+
+/// The Z-reports in the period from dinkassa.se, not yet looked up in Bokio.
+fn hamta_zrapporter(args: &Cli, dinkassa: &DinKassa) -> Result<(Vec<RapportImport>, DateRange), Error> {
+    let interval = DateRange::new(&args.start_date, &args.end_date);
     let mut importer: Vec<RapportImport> = Vec::new();
     let sie_listing = dinkassa.list_sie_reports(&interval)?;
     let report_listing = dinkassa.list_zreports(&interval)?;
+    let mut matchade: Vec<String> = Vec::new();
     for sie in sie_listing.zreports {
-        let title = sie.verifikatnamn().to_lowercase();
-        let nr = sie.number().expect(&format!("Kunde inte tolka rapport {}", sie.zreport));
-        let report = report_listing.items.iter()
-            .find(|e| e.number == nr)
-            .expect(&format!("Z-Rapport {} hittades inte", nr))
-            .clone();
-        let verifikat = journal
-            .iter()
-            .find(|e| e.title.to_lowercase() == title && e.reversed_by_journal_entry_id.is_none())
-            .cloned();
+        let Ok(key) = sie.key() else {
+            eprintln!("Varning: Kunde inte tolka Z-Rapport {}, hoppar över", sie.zreport);
+            continue;
+        };
+        let report = match hitta_zrapport(&key, &report_listing.items, &matchade) {
+            Ok(report) => report.clone(),
+            Err(msg) => {
+                eprintln!("Varning: {}, hoppar över", msg);
+                continue;
+            }
+        };
+        matchade.push(report.id.clone());
+        let konton = args.kontomappning.apply(&args.bokio_company_id, key.register, &sie.accounts);
         importer.push(RapportImport {
+            key,
             sie,
+            konton,
             report,
-            verifikat,
+            verifikat: None,
+            importerad: false,
+            forhandsgranskad: false,
+            fel: None,
         })
     }
 
     Ok((importer, interval))
 }
 
-fn rakna_importerade_rapporter(importer: &Vec<RapportImport>) -> usize {
+/// The journal entry recorded for a Z-report, unless it has been reversed or deleted
+/// since.
+fn hamta_registrerat_verifikat(
+    bokforing: &dyn AccountingBackend,
+    post: &ImportRecord,
+) -> Result<Option<JournalEntry>, Error> {
+    match bokforing.get_journal_entry(&post.journal_entry_id)? {
+        Some(verifikat) if verifikat.reversed_by_journal_entry_id.is_none() => Ok(Some(verifikat)),
+        Some(_) => Ok(None),
+        None => {
+            eprintln!(
+                "Varning: verifikat {} för Z-Rapport K{}:{} finns inte i {}, kör esbokio reconcile",
+                post.journal_entry_number, post.register, post.number, bokforing.name()
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Pairs a SIE report with its Z-report on register, number and date. The register
+/// is only compared when the Z-report listing includes it.
+fn hitta_zrapport<'a>(
+    key: &ZReportKey,
+    listing: &'a [ZReportListItem],
+    matchade: &[String],
+) -> Result<&'a ZReportListItem, String> {
+    let kandidater: Vec<&ZReportListItem> = listing
+        .iter()
+        .filter(|e| {
+            e.number == key.number
+                && e.datum() == key.date
+                && e.register.is_none_or(|register| register == key.register)
+                && !matchade.contains(&e.id)
+        })
+        .collect();
+
+    match kandidater[..] {
+        [report] => Ok(report),
+        [] => Err(format!("Z-Rapport {} hittades inte", key)),
+        _ => Err(format!("Z-Rapport {} är tvetydig, {} möjliga rapporter", key, kandidater.len())),
+    }
+}
+
+fn rakna_importerade_rapporter(importer: &[RapportImport]) -> usize {
     importer.iter().filter(|e| e.verifikat.is_some()).count()
 }
 
-fn lista_rapporter(importer: &Vec<RapportImport>) {
+fn lista_rapporter(importer: &[RapportImport], args: &Cli) {
     let mut builder = Builder::default();
     let mut account_names: HashMap<String, String> = HashMap::new();
     for e in importer {
-        for acc in e.sie.accounts.iter() {
+        for acc in e.konton.iter() {
             if !account_names.contains_key(&acc.number) && (acc.number.starts_with("1")) {
                 account_names.insert(acc.number.clone(), acc.description.to_uppercase());
             }
@@ -106,7 +224,7 @@ fn lista_rapporter(importer: &Vec<RapportImport>) {
     let mut accounts: Vec<String> = account_names.keys().map(|k| k.to_string()).collect();
     let mut account_totals: Vec<Decimal> = repeat_n(Decimal::ZERO, accounts.len()).collect();
     accounts.sort();
-    let fixed_columns = ["VERIFIKAT", "Z-RAPPORT", "DATUM"];
+    let fixed_columns = ["VERIFIKAT", "KASSA", "Z-RAPPORT", "DATUM"];
     builder.push_record(fixed_columns.iter().map(|a| a.to_string())
         .chain(accounts.iter().map(|a| account_names[a].clone()))
         .chain(once("TOTAL".to_string())));
@@ -117,11 +235,12 @@ fn lista_rapporter(importer: &Vec<RapportImport>) {
             .clone()
             .map_or("".to_string(), |j| j.journal_entry_number + " ✓");
         let datum = rapport.datum();
+        let kassa = format!("K{}", e.key.register);
         let number = e.report.number.to_string();
-        let mut values: Vec<String> = [vernr, number, datum].to_vec();
+        let mut values: Vec<String> = [vernr, kassa, number, datum].to_vec();
         let mut total = dec!(0);
         for (i, acc) in accounts.iter().enumerate() {
-            let amount = rapport.konto(&acc).unwrap_or(Decimal::ZERO);
+            let amount = e.konton.iter().find(|a| &a.number == acc).map_or(Decimal::ZERO, |a| a.amount);
             values.push(money(amount));
             account_totals[i] += amount;
             if amount.is_sign_positive() {
@@ -145,15 +264,123 @@ fn lista_rapporter(importer: &Vec<RapportImport>) {
     table.with((Alignment::right(), Padding::new(2, 2, 0, 0)));
     table.with(Style::modern_rounded());
     println!("{}", table);
+
+    let mut registers: Vec<u32> = importer.iter().map(|e| e.key.register).collect();
+    registers.sort();
+    registers.dedup();
+    for register in registers {
+        let accounts = importer
+            .iter()
+            .filter(|e| e.key.register == register)
+            .flat_map(|e| e.sie.accounts.iter());
+        let rules: Vec<String> = args
+            .kontomappning
+            .effective(&args.bokio_company_id, register, accounts)
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        if !rules.is_empty() {
+            println!("Kontomappning K{}: {}", register, rules.join("; "));
+        }
+    }
+}
+
+fn valj_kassor(kassa: &DinKassa, val: &str) -> Result<Vec<Machine>, Error> {
+    if val == "all" || val == "alla" {
+        if kassa.machines.is_empty() {
+            return Err(Error::Validation("Inga kassor finns på kontot".to_string()));
+        }
+        return Ok(kassa.machines.clone());
+    }
+
+    if !val.is_empty() {
+        if let Some(machine) = kassa.find_machine(val) {
+            return Ok(vec![machine.clone()]);
+        }
+
+        let kassor: Vec<String> = kassa.machines.iter().map(|m| format!("{} ({})", m.name, m.id)).collect();
+        return Err(Error::Validation(format!(
+            "Kassan {} hittades inte, tillgängliga kassor: {}",
+            val,
+            if kassor.is_empty() { "inga".to_string() } else { kassor.join(", ") }
+        )));
+    }
+
+    if kassa.machines.len() <= 1 || !std::io::stdin().is_terminal() {
+        return Ok(vec![kassa.machine.clone()]);
+    }
+
+    for (i, m) in kassa.machines.iter().enumerate() {
+        let standard = if m.id == kassa.machine.id { "*" } else { "" };
+        println!("{:>3}. {}{}", i + 1, m.name, standard);
+    }
+
+    loop {
+        print!("Välj kassa ([A]lla, [Q] = avsluta eller nummer)? ");
+        std::io::stdout().flush().unwrap();
+
+        let mut input = String::new();
+        if let Ok(size) = std::io::stdin().read_line(&mut input) {
+            if size == 0 {
+                // EOF
+                return Ok(Vec::new());
+            }
+
+            if input == "\n" {
+                return Ok(vec![kassa.machine.clone()]);
+            }
+
+            input = input.trim().to_lowercase();
+            if input == "a" {
+                return Ok(kassa.machines.clone());
+            }
+
+            if input == "q" || input == "n" {
+                return Ok(Vec::new());
+            }
+
+            if let Ok(n) = input.parse::<usize>()
+                && n >= 1
+                && n <= kassa.machines.len()
+            {
+                return Ok(vec![kassa.machines[n - 1].clone()]);
+            }
+
+            println!("Ogiltigt val: {}", input);
+        }
+    }
 }
 
-fn valj_rapporter(rapporter: &Vec<RapportImport>) -> Vec<u32> {
-    let mojliga: Vec<u32> = rapporter
+fn ej_importerade(rapporter: &[RapportImport]) -> Vec<ZReportKey> {
+    rapporter
         .iter()
         .filter(|e| e.verifikat.is_none())
-        .map(|e| e.report.number)
-        .collect();
+        .map(|e| e.key.clone())
+        .collect()
+}
 
+/// Reports matching the values given to --only that are not imported yet. Values
+/// that matched a report are added to `hittade`.
+fn valda_rapporter(rapporter: &[RapportImport], val: &[String], hittade: &mut Vec<String>) -> Vec<ZReportKey> {
+    let alla: Vec<ZReportKey> = rapporter.iter().map(|e| e.key.clone()).collect();
+    let mut valda: Vec<ZReportKey> = Vec::new();
+    for part in val {
+        let Some(key) = tolka_val(part, &alla) else {
+            continue;
+        };
+        if !hittade.contains(part) {
+            hittade.push(part.clone());
+        }
+        let importerad = rapporter.iter().any(|e| &e.key == key && e.verifikat.is_some());
+        if !importerad && !valda.contains(key) {
+            valda.push(key.clone());
+        }
+    }
+    valda
+}
+
+fn valj_rapporter(rapporter: &[RapportImport]) -> Vec<ZReportKey> {
+    let mojliga = ej_importerade(rapporter);
     if mojliga.is_empty() {
         return Vec::new();
     }
@@ -186,13 +413,11 @@ fn valj_rapporter(rapporter: &Vec<RapportImport>) -> Vec<u32> {
                 return Vec::new();
             }
 
-            let mut valda: Vec<u32> = Vec::new();
+            let mut valda: Vec<ZReportKey> = Vec::new();
             for part in input.split_whitespace() {
-                if let Ok(n) = part.parse::<u32>()
-                    && mojliga.contains(&n)
-                {
-                    if !valda.contains(&n) {
-                        valda.push(n);
+                if let Some(key) = tolka_val(part, &mojliga) {
+                    if !valda.contains(key) {
+                        valda.push(key.clone());
                     }
                 } else {
                     println!("Ogiltigt val: {}", part);
@@ -208,138 +433,429 @@ fn valj_rapporter(rapporter: &Vec<RapportImport>) -> Vec<u32> {
     }
 }
 
-fn create_journal_entry(rapport: &SIEReportListItem) -> CreateJournal {
+/// Interprets "42" or "k1:42" as one of the selectable reports. A bare number
+/// is only accepted when it is unique among the registers.
+fn tolka_val<'a>(part: &str, mojliga: &'a [ZReportKey]) -> Option<&'a ZReportKey> {
+    let (register, number) = tolka_nummer(part)?;
+    let mut kandidater = mojliga
+        .iter()
+        .filter(|k| k.number == number && register.is_none_or(|r| r == k.register));
+    let key = kandidater.next()?;
+    if kandidater.next().is_some() {
+        return None;
+    }
+    Some(key)
+}
+
+/// Builds the journal entry for a Z-report. A difference between debit and credit of
+/// less than one krona is booked on `avrundningskonto` when given, any other
+/// difference is rejected.
+fn create_journal_entry(
+    rapport: &SIEReportListItem,
+    konton: &[MappedAccount],
+    avrundningskonto: Option<i32>,
+) -> Result<NewJournalEntry, Error> {
     let title = rapport.verifikatnamn();
     let date = rapport.datum();
-    let mut items: Vec<CreateJournalAccount> =
-        Vec::with_capacity(rapport.accounts.len());
-    for tr in rapport.accounts.iter() {
+    let mut items: Vec<NewJournalLine> =
+        Vec::with_capacity(konton.len() + 1);
+    for tr in konton.iter() {
         let debit = tr.amount.max(Decimal::ZERO);
         let credit = tr.amount.min(Decimal::ZERO).abs();
-        let account = i32::from_str(&tr.number).unwrap();
-        items.push(CreateJournalAccount {
+        let account = tolka_konto(&tr.number).ok_or_else(|| {
+            Error::Validation(format!(
+                "Ogiltigt kontonummer \"{}\" ({}), kontot måste ha fyra siffror",
+                tr.number, tr.description
+            ))
+        })?;
+        if tr.amount.scale() > 2 && tr.amount.round_dp(2) != tr.amount {
+            return Err(Error::Validation(format!(
+                "Beloppet {} på konto {} har fler än två decimaler",
+                tr.amount, tr.number
+            )));
+        }
+        items.push(NewJournalLine {
             account,
             debit,
             credit,
+            name: tr.description.clone(),
         });
     }
 
-    CreateJournal { title, date, items }
+    if items.is_empty() {
+        return Err(Error::Validation("Z-Rapporten saknar konteringar".to_string()));
+    }
+
+    let debet: Decimal = items.iter().map(|i| i.debit).sum();
+    let kredit: Decimal = items.iter().map(|i| i.credit).sum();
+    let differens = debet - kredit;
+    if !differens.is_zero() {
+        let Some(account) = avrundningskonto.filter(|_| differens.abs() < Decimal::ONE) else {
+            return Err(Error::Validation(format!(
+                "Verifikatet är inte balanserat: debet {}, kredit {}, differens {}",
+                money(debet), money(kredit), money(differens)
+            )));
+        };
+        items.push(NewJournalLine {
+            account,
+            debit: (-differens).max(Decimal::ZERO),
+            credit: differens.max(Decimal::ZERO),
+            name: "Öresavrundning".to_string(),
+        });
+    }
+
+    Ok(NewJournalEntry { title, date, items, company_name: String::new() })
 }
 
+/// Checks that every account in the journal entry exists in the company's chart of
+/// accounts in Bokio. The chart is fetched again before giving up, the account may
+/// have been added since it was saved. Nothing is checked without a chart of accounts.
+fn kontrollera_konton(
+    bokforing: &dyn AccountingBackend,
+    verifikat: &NewJournalEntry,
+    konton: &[MappedAccount],
+) -> Result<(), Error> {
+    let saknade = |kontoplan: &[Account]| -> Vec<i32> {
+        let mut saknade: Vec<i32> = Vec::new();
+        for item in verifikat.items.iter() {
+            if !kontoplan.iter().any(|k| k.account == item.account) && !saknade.contains(&item.account) {
+                saknade.push(item.account);
+            }
+        }
+        saknade
+    };
+
+    let Some(kontoplan) = bokforing.chart_of_accounts(false)? else {
+        return Ok(());
+    };
+    if saknade(&kontoplan).is_empty() {
+        return Ok(());
+    }
+    let kontoplan = bokforing.chart_of_accounts(true)?.unwrap_or(kontoplan);
+    let saknade = saknade(&kontoplan);
+    if saknade.is_empty() {
+        return Ok(());
+    }
+
+    let mut msg = "Konton saknas i Bokios kontoplan:".to_string();
+    for nummer in saknade {
+        let konto = konton.iter().find(|k| k.number == nummer.to_string());
+        msg += &format!("\n  {}", nummer);
+        if let Some(konto) = konto {
+            msg += &format!(" {}", konto.description);
+        }
+        if let Some(forslag) = foresla_konto(nummer, &kontoplan) {
+            let fran = konto.map_or(nummer.to_string(), |k| k.sources.join("\", \""));
+            msg += &format!(
+                ", förslag: {} {} ([[rule]] from = [\"{}\"], to = \"{}\")",
+                forslag.account, forslag.name, fran, forslag.account
+            );
+        }
+    }
+    Err(Error::Validation(msg))
+}
+
+/// The closest account in the same account group (first three digits), or the same
+/// account class (first two digits).
+fn foresla_konto(nummer: i32, kontoplan: &[Account]) -> Option<&Account> {
+    [10, 100].iter().find_map(|grupp| {
+        kontoplan
+            .iter()
+            .filter(|k| k.account / grupp == nummer / grupp)
+            .min_by_key(|k| (k.account - nummer).abs())
+    })
+}
+
+/// Prints the journal lines that would be sent to Bokio. Mapped lines show the
+/// ES Kassa accounts they come from.
+fn visa_verifikat(konton: &[MappedAccount], verifikat: &NewJournalEntry, avrundningskonto: Option<i32>) {
+    println!("{} {}", verifikat.title, verifikat.date);
+    let mut builder = Builder::default();
+    builder.push_record(["KONTO", "BENÄMNING", "DEBET", "KREDIT", "FRÅN"]);
+    for item in verifikat.items.iter() {
+        let konto = konton.iter().find(|a| a.number == item.account.to_string());
+        let benamning = konto
+            .map_or(if avrundningskonto == Some(item.account) { "Avrundning" } else { "" }, |a| a.description.as_str());
+        let fran = konto.filter(|a| a.is_mapped()).map_or(String::new(), |a| a.sources.join(", "));
+        builder.push_record([
+            item.account.to_string(),
+            benamning.to_string(),
+            money(item.debit),
+            money(item.credit),
+            fran,
+        ]);
+    }
+    let debet: Decimal = verifikat.items.iter().map(|i| i.debit).sum();
+    let kredit: Decimal = verifikat.items.iter().map(|i| i.credit).sum();
+    builder.push_record(["SUMMA".to_string(), "".to_string(), money(debet), money(kredit), "".to_string()]);
+
+    let mut table = builder.build();
+    table.with((Alignment::right(), Padding::new(2, 2, 0, 0)));
+    table.with(Style::modern_rounded());
+    println!("{}", table);
+}
+
+/// Fetches the files for a Z-report and books it. With --dry-run the
+/// journal entry is only shown and saved as .bokio.json, and `None` is returned.
+/// A failed upload of the PDF is kept in `import.fel`, the entry is booked anyway.
 fn importera_rapport(
     kassa: &DinKassa,
-    bokio: &Bokio,
-    import: &RapportImport,
-    save_files: bool,
-) -> Result<JournalEntry, String> {
-    println!(
-        "Importerar Z-Rapport {}...",
-        import.report.number
-    );
+    bokforing: &dyn AccountingBackend,
+    import: &mut RapportImport,
+    args: &Cli,
+) -> Result<Option<JournalEntry>, Error> {
+    let save_files = args.save_files;
+    if args.dry_run {
+        println!("Förhandsgranskar Z-Rapport {}...", import.report.number);
+    } else {
+        println!(
+            "Importerar Z-Rapport {}...",
+            import.report.number
+        );
+    }
 
-    let basename = kassa.zreport_basename(&import.report);
+    let basename = match &args.output_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).map_err(|e| Error::io(dir.display(), e))?;
+            dir.join(kassa.zreport_basename(import.report.number)).display().to_string()
+        }
+        None => kassa.zreport_basename(import.report.number),
+    };
     let pdf_filename = format!("{}.pdf", basename);
     let json_filename = format!("{}.json", basename);
     let sie4_filename = format!("{}.si", basename);
+    // Checked before any files are downloaded, so that an entry that cannot be booked leaves nothing behind
+    let mut journal_entry = create_journal_entry(&import.sie, &import.konton, args.avrundningskonto)?;
+    journal_entry.company_name = kassa.machine.customer_name.clone();
+    kontrollera_konton(bokforing, &journal_entry, &import.konton)?;
+    let mut post = ImportRecord::new(
+        &args.bokforing_id(),
+        &kassa.machine.id,
+        &import.report,
+        &import.key,
+        &import.sie,
+    );
 
     print!("* Hämtar PDF... ");
     std::io::stdout().flush().ok();
-    let pdf = kassa.zreport_pdf(&import.report.id).map_err(|e| {
-        format!(
-            "Kunde inte hämta PDF för Z-Rapport {}: {}",
-            import.report.number, e
-        )
-    })?;
+    let pdf = kassa.zreport_pdf(&import.report.id)?;
 
     println!("{}", pdf_filename);
-    std::fs::write(&pdf_filename, pdf).expect("Kunde inte spara PDF.");
+    std::fs::write(&pdf_filename, pdf).map_err(|e| Error::io(&pdf_filename, e))?;
+    post.pdf = Some(pdf_filename.clone());
+    registrera_steg(args, &mut post, Step::PdfFetched);
 
     print!("* Hämtar SIE4... ");
     std::io::stdout().flush().ok();
-    let sie4 = kassa.zreport_sie(&import.report.id).map_err(|e| {
-        format!(
-            "Kunde inte hämta SIE4 för Z-Rapport {}: {}",
-            import.report.number, e
-        )
-    })?;
+    let sie4 = kassa.zreport_sie(&import.report.id)?;
 
     println!("{}", sie4_filename);
-    std::fs::write(&sie4_filename, sie4).expect("Kunde inte spara SIE4.");
+    let skillnader = match sie::parse(&sie4) {
+        Ok(sie4) => jamfor_sie4(&sie4, &import.sie),
+        Err(err) => vec![err.to_string()],
+    };
+    std::fs::write(&sie4_filename, sie4).map_err(|e| Error::io(&sie4_filename, e))?;
+    registrera_steg(args, &mut post, Step::Sie4Fetched);
+    if !skillnader.is_empty()
+        && let Err(err) = godkann_skillnader(args, &sie4_filename, &skillnader)
+    {
+        // Nothing is booked, so the files and the record of the fetched files are removed
+        if !save_files {
+            ta_bort_filer(&[&pdf_filename, &sie4_filename]);
+        }
+        let mut tillstand = args.tillstand.borrow_mut();
+        tillstand.remove(&post.company_id, &post.zreport_id);
+        spara_tillstand(&tillstand);
+        return Err(err);
+    }
 
     let json = serde_json::to_vec_pretty(&import.sie).unwrap();
     if save_files {
         print!("* Sparar {}...", json_filename);
         std::io::stdout().flush().ok();
     }
-    std::fs::write(&json_filename, json).expect("Kunde inte spara JSON.");
+    std::fs::write(&json_filename, json).map_err(|e| Error::io(&json_filename, e))?;
 
-    let journal_entry = create_journal_entry(&import.sie);
     let bokio_json_filename = format!("{}.bokio.json", basename);
     if save_files {
         print!(" {}", bokio_json_filename);
         std::io::stdout().flush().ok();
     }
-    let json = serde_json::to_vec_pretty(&journal_entry).unwrap();
-    std::fs::write(&bokio_json_filename, json).expect("Kunde inte spara JSON.");
+    let json = serde_json::to_vec_pretty(&CreateJournal::from(&journal_entry)).unwrap();
+    std::fs::write(&bokio_json_filename, json).map_err(|e| Error::io(&bokio_json_filename, e))?;
     if save_files {
         println!();
     }
 
+    if args.dry_run {
+        println!();
+        visa_verifikat(&import.konton, &journal_entry, args.avrundningskonto);
+        println!("Underlag: {} (application/pdf)", pdf_filename);
+        println!("Verifikatet sparades i {}, inget bokfördes i {}", bokio_json_filename, bokforing.name());
+        if !save_files {
+            ta_bort_filer(&[&pdf_filename, &json_filename, &sie4_filename]);
+        }
+        println!();
+        return Ok(None);
+    }
+
     print!("* Bokför Z-Rapport {}... ", import.report.number);
     std::io::stdout().flush().ok();
-    let journal_entry = bokio.create_journal_entry(&journal_entry).map_err(|e| {
-        format!(
-            "Kunde inte bokföra verifikat för Z-Rapport {}: {}",
-            import.report.number, e
-        )
-    })?;
+    let journal_entry = bokforing.create_journal_entry(&journal_entry)?;
     println!("{}", journal_entry.journal_entry_number);
+    post.set_journal_entry(&journal_entry);
+    registrera_steg(args, &mut post, Step::EntryCreated);
 
     print!("* Laddar upp underlag... ");
     std::io::stdout().flush().ok();
-    bokio
-        .upload(&pdf_filename, "application/pdf", &journal_entry.id)
-        .inspect_err(|e| eprintln!("Misslyckades: {}", e))
-        .inspect(|_| println!("OK"))
-        .ok();
+    let uppladdad = match bokforing.upload(&pdf_filename, "application/pdf", &journal_entry.id) {
+        Ok(upload) => {
+            println!("OK");
+            post.uploads.push(upload.id);
+            post.pdf = None;
+            registrera_steg(args, &mut post, Step::AttachmentUploaded);
+            true
+        }
+        Err(e) => {
+            eprintln!("Misslyckades: {}", e);
+            eprintln!("Underlaget sparas i {}, ladda upp det senare med esbokio resume", pdf_filename);
+            post.status = Some(ImportStatus::AttachmentMissing);
+            registrera(args, post);
+            import.fel = Some(e);
+            false
+        }
+    };
 
     if !save_files {
-        let files = [&pdf_filename, &json_filename, &sie4_filename, &bokio_json_filename];
-        for f in files {
-            std::fs::exists(f).and_then(|_| std::fs::remove_file(f))
-                .inspect_err(|e| eprintln!("Misslyckades att radera: {}", e))
-                .ok();
+        ta_bort_filer(&[&json_filename, &sie4_filename, &bokio_json_filename]);
+        if uppladdad {
+            ta_bort_filer(&[&pdf_filename]);
         }
     }
 
     println!();
-    Ok(journal_entry)
+    Ok(Some(journal_entry))
 }
 
+/// Compares the #TRANS rows in the SIE4 file with the accounts in the JSON report
+/// the journal entry is created from.
+fn jamfor_sie4(sie4: &Sie, rapport: &SIEReportListItem) -> Vec<String> {
+    let mut konton: BTreeMap<String, (Decimal, Decimal)> = sie4
+        .account_totals()
+        .into_iter()
+        .map(|(konto, belopp)| (konto, (belopp, Decimal::ZERO)))
+        .collect();
+    for account in rapport.accounts.iter() {
+        konton.entry(account.number.clone()).or_default().1 += account.amount;
+    }
+
+    konton
+        .iter()
+        .filter(|(_, (sie4, json))| sie4 != json)
+        .map(|(konto, (sie4, json))| {
+            format!("konto {}: {} i SIE4-filen, {} enligt Z-Rapporten", konto, money(*sie4), money(*json))
+        })
+        .collect()
+}
+
+/// Shows how the SIE4 file differs from the Z-report. The report is only booked if
+/// the user accepts the differences when asked, and never with --yes or --only.
+fn godkann_skillnader(args: &Cli, sie4_filename: &str, skillnader: &[String]) -> Result<(), Error> {
+    eprintln!("Varning: {} stämmer inte med Z-Rapporten:", sie4_filename);
+    for skillnad in skillnader {
+        eprintln!("  {}", skillnad);
+    }
+    if args.dry_run || (args.interaktiv && bekrafta("Bokför ändå (j/N)? ")) {
+        return Ok(());
+    }
+    Err(Error::Validation(format!("{} stämmer inte med Z-Rapporten", sie4_filename)))
+}
+
+/// Records that a step of an import is done, except with --dry-run.
+fn registrera_steg(args: &Cli, post: &mut ImportRecord, step: Step) {
+    post.steps.push(step);
+    if !args.dry_run {
+        registrera(args, post.clone());
+    }
+}
+
+/// Records an import in the local state.
+fn registrera(args: &Cli, post: ImportRecord) {
+    let mut tillstand = args.tillstand.borrow_mut();
+    tillstand.record(post);
+    spara_tillstand(&tillstand);
+}
+
+/// The journal entry is already booked or reversed, so a failure to save is only reported.
+fn spara_tillstand(tillstand: &State) {
+    tillstand
+        .save()
+        .inspect_err(|e| eprintln!("Kunde inte spara importerade Z-Rapporter: {}", e))
+        .ok();
+}
+
+fn ta_bort_filer(files: &[&String]) {
+    for f in files {
+        std::fs::exists(f).and_then(|_| std::fs::remove_file(f))
+            .inspect_err(|e| eprintln!("Misslyckades att radera: {}", e))
+            .ok();
+    }
+}
+
+/// Imports `valda` without asking, or shows the menu until nothing more is chosen
+/// when `valda` is `None`.
 fn importera(kassa: &DinKassa,
-             bokio: &Bokio, rapporter: &mut Vec<RapportImport>,
-             save_files: bool,
+             bokforing: &dyn AccountingBackend, rapporter: &mut [RapportImport],
+             valda: Option<Vec<ZReportKey>>,
+             args: &Cli,
 ) {
+    if let Some(valda) = valda {
+        lista_rapporter(rapporter, args);
+        importera_valda(kassa, bokforing, rapporter, valda, args);
+        return;
+    }
+
     loop {
-        lista_rapporter(&rapporter);
-        let valda = valj_rapporter(&rapporter);
+        lista_rapporter(rapporter, args);
+        let valda = valj_rapporter(rapporter);
         if valda.is_empty() {
             break;
         }
+        importera_valda(kassa, bokforing, rapporter, valda, args);
+        if args.dry_run {
+            break;
+        }
+    }
+}
 
-        for seqnr in valda {
-            let imp = rapporter
-                .iter_mut()
-                .find(|e| e.report.number == seqnr)
-                .unwrap();
-            println!();
-            match importera_rapport(&kassa, &bokio, imp, save_files) {
-                Ok(journal_entry) => {
-                    imp.verifikat.replace(journal_entry);
-                }
-                Err(msg) => {
-                    eprintln!("{}", msg);
+/// Stops at the first report that fails, except for reports that cannot be booked.
+/// The error is kept in the report.
+fn importera_valda(kassa: &DinKassa,
+                   bokforing: &dyn AccountingBackend, rapporter: &mut [RapportImport],
+                   valda: Vec<ZReportKey>,
+                   args: &Cli,
+) {
+    for key in valda {
+        let imp = rapporter
+            .iter_mut()
+            .find(|e| e.key == key)
+            .unwrap();
+        println!();
+        imp.fel = None;
+        match importera_rapport(kassa, bokforing, imp, args) {
+            Ok(Some(journal_entry)) => {
+                imp.verifikat.replace(journal_entry);
+                imp.importerad = true;
+            }
+            Ok(None) => {
+                imp.forhandsgranskad = true;
+            }
+            Err(err) => {
+                println!();
+                eprintln!("Kunde inte importera Z-Rapport {}: {}", key, err);
+                let fortsatt = fortsatt_efter(&err);
+                imp.fel = Some(err);
+                if !fortsatt {
                     break;
                 }
             }
@@ -347,118 +863,1277 @@ fn importera(kassa: &DinKassa,
     }
 }
 
-fn main() {
-    let mut args = Cli {
-        start_date: None,
-        end_date: None,
-        dinkassa_username: utils::get_env("DINKASSA_USERNAME"),
-        dinkassa_password: utils::get_env("DINKASSA_PASSWORD"),
-        bokio_api_url: utils::get_env_or_default("BOKIO_API_URL", BOKIO_API_URL),
-        bokio_api_token: utils::get_env("BOKIO_API_TOKEN"),
-        bokio_company_id: utils::get_env("BOKIO_COMPANY_ID"),
-        save_files: false,
-    };
+/// Whether to import the next report after `err`. A report that fails validation is
+/// not booked and says nothing about the others, while e.g. a network error would
+/// likely fail the next report too.
+fn fortsatt_efter(err: &Error) -> bool {
+    matches!(err, Error::Validation(_))
+}
 
-    let mut iter = std::env::args().skip(1);
-    while let Some(arg) = iter.next() {
-        if let Some(username) = check_arg("dinkassa-username", &arg, &mut iter) {
-            args.dinkassa_username = username;
-        } else if let Some(password) = check_arg("dinkassa-password", &arg, &mut iter) {
-            args.dinkassa_password = password;
-        } else if let Some(start) = check_arg("date", &arg, &mut iter).map(to_date) {
-            args.start_date = Some(start);
-            args.end_date = Some(start);
-        } else if let Some(start) = check_arg("start", &arg, &mut iter).map(to_date) {
-            args.start_date = Some(start);
-        } else if let Some(end) = check_arg("end", &arg, &mut iter).map(to_date) {
-            args.end_date = Some(end);
-        } else if let Some(url) = check_arg("bokio-api-url", &arg, &mut iter) {
-            args.bokio_api_url = url;
-        } else if let Some(token) = check_arg("bokio-api-token", &arg, &mut iter) {
-            args.bokio_api_token = token;
-        } else if let Some(company_id) = check_arg("bokio-company-id", &arg, &mut iter) {
-            args.bokio_company_id = company_id;
-        } else if arg == "--save" || arg == "--save-files" {
-            args.save_files = true;
-        } else {
-            eprintln!("{}: invalid option", arg);
-            std::process::exit(1);
+#[derive(Default, Serialize)]
+struct Summary {
+    imported: usize,
+    already_imported: usize,
+    attachment_missing: usize,
+    failed: usize,
+    reports: Vec<ReportSummary>,
+}
+
+#[derive(Serialize)]
+struct ReportSummary {
+    machine: String,
+    register: u32,
+    number: u32,
+    date: String,
+    /// imported, already_imported, attachment_missing, dry_run, failed or skipped
+    status: &'static str,
+    journal_entry: Option<String>,
+    error: Option<String>,
+}
+
+impl ReportSummary {
+    fn new(machine: &Machine, import: &RapportImport) -> Self {
+        let status = match (&import.verifikat, &import.fel) {
+            (Some(_), Some(_)) => "attachment_missing",
+            (Some(_), None) if import.importerad => "imported",
+            (Some(_), _) => "already_imported",
+            (None, Some(_)) => "failed",
+            (None, None) if import.forhandsgranskad => "dry_run",
+            (None, None) => "skipped",
+        };
+        ReportSummary {
+            machine: machine.name.clone(),
+            register: import.key.register,
+            number: import.key.number,
+            date: import.key.date.clone(),
+            status,
+            journal_entry: import.verifikat.as_ref().map(|v| v.journal_entry_number.clone()),
+            error: import.fel.as_ref().map(|e| e.to_string()),
+        }
+    }
+}
+
+impl Summary {
+    /// Counts the reports and writes the summary as JSON to a file.
+    fn skriv(&mut self, path: &Path) -> Result<(), Error> {
+        let antal = |status: &str| self.reports.iter().filter(|r| r.status == status).count();
+        self.imported = antal("imported");
+        self.already_imported = antal("already_imported");
+        self.attachment_missing = antal("attachment_missing");
+        self.failed = antal("failed");
+
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n").map_err(|e| Error::io(path.display(), e))
+    }
+}
+
+/// Exits instead of prompting for missing credentials when running non-interactively.
+fn saknas(namn: &str) -> ! {
+    avsluta(&format!("{} saknas", namn), Error::Auth(String::new()));
+}
+
+
+impl Cli {
+    fn new(options: GlobalOptions, command: &Command) -> Self {
+        let config_path = options
+            .config
+            .clone()
+            .or_else(|| utils::config_dir().map(|dir| dir.join("config.toml")));
+        let config = match &config_path {
+            Some(path) if options.config.is_some() || path.exists() => {
+                Config::load(path).unwrap_or_else(|err| avsluta("Konfiguration", err))
+            }
+            _ => Config::default(),
+        };
+        let ingen_profil = Profile::default();
+        let (profilnamn, profil) = match config.profile(options.profile.as_deref()) {
+            Ok(Some((namn, profil))) => (Some(namn), profil),
+            Ok(None) => (None, &ingen_profil),
+            Err(err) => avsluta("Konfiguration", err),
+        };
+        // login asks for new values and should not run commands or read the keyring
+        let login = matches!(command, Command::Login);
+        // The value, a file or command on the command line or in environment variables,
+        // the profile and last the keyring. Only values are taken here, the others are
+        // read when needed so that unused commands are not run.
+        let mut hemliga_kallor = HashMap::new();
+        let mut hemlighet = |namn: &'static str, value: Option<String>, file: Option<PathBuf>, cmd: Option<String>,
+                             profil: &Option<Secret>| {
+            if value.is_none() && !login {
+                let kalla = file.map(Secret::File).or(cmd.map(Secret::Command)).or_else(|| profil.clone());
+                hemliga_kallor.insert(namn, kalla);
+            }
+            value.unwrap_or_default()
+        };
+        let dinkassa_password = hemlighet(
+            "DINKASSA_PASSWORD",
+            options.dinkassa_password,
+            options.dinkassa_password_file,
+            options.dinkassa_password_command,
+            &profil.dinkassa_password,
+        );
+        let dinkassa_machine_key = hemlighet(
+            "DINKASSA_MACHINE_KEY",
+            options.dinkassa_machine_key,
+            options.dinkassa_machine_key_file,
+            options.dinkassa_machine_key_command,
+            &profil.dinkassa_machine_key,
+        );
+        let bokio_api_token = hemlighet(
+            "BOKIO_API_TOKEN",
+            options.bokio_api_token,
+            options.bokio_api_token_file,
+            options.bokio_api_token_command,
+            &profil.bokio_api_token,
+        );
+
+        let to_date = |flagga: &str, s: Option<String>| {
+            s.map(|s| to_date(s).unwrap_or_else(|e| avsluta(flagga, e)))
+        };
+        let (start_date, end_date) = match (options.date, options.start, profil.lookback_days) {
+            (Some(date), _, _) => {
+                let date = to_date("--date", Some(date));
+                (date, date)
+            }
+            (None, None, Some(days)) => {
+                let today = chrono::Local::now().date_naive();
+                let end_date = to_date("--end", options.end).unwrap_or(today);
+                (end_date.checked_sub_days(Days::new(days)), Some(end_date))
+            }
+            (None, start, _) => (to_date("--start", start), to_date("--end", options.end)),
+        };
+
+        let kontomappning = match options.account_map.as_ref().or(profil.account_map.as_ref()) {
+            Some(path) => AccountMap::load(path).unwrap_or_else(|err| avsluta("Kontomappning", err)),
+            None => AccountMap::default(),
+        };
+        let avrundningskonto = options
+            .rounding_account
+            .or_else(|| profil.rounding_account.as_deref().and_then(tolka_konto))
+            .filter(|_| !options.no_rounding_account);
+        let tillstand = match options.state.or_else(|| utils::data_dir().map(|dir| dir.join("state.json"))) {
+            Some(path) => {
+                State::load(&path).unwrap_or_else(|err| avsluta("Kunde inte läsa importerade Z-Rapporter", err))
+            }
+            None => State::default(),
+        };
+        let ledger = options.ledger.or(profil.ledger.clone());
+        let bokio_company_id = options.bokio_company_id.or(profil.bokio_company_id.clone()).unwrap_or_default();
+        // Profiles usually log in as different users, so they get a session each
+        let session_fil = match profilnamn {
+            Some(namn) => format!("dinkassa-session-{}.json", namn),
+            None => "dinkassa-session.json".to_string(),
+        };
+
+        let mut args = Cli {
+            dinkassa_api_url: options.dinkassa_api_url,
+            dinkassa_username: options.dinkassa_username.or(profil.dinkassa_username.clone()).unwrap_or_default(),
+            dinkassa_password,
+            dinkassa_integrator_id: options
+                .dinkassa_integrator_id
+                .or(profil.dinkassa_integrator_id.clone())
+                .unwrap_or_default(),
+            dinkassa_machine_id: options
+                .dinkassa_machine_id
+                .or(profil.dinkassa_machine_id.clone())
+                .unwrap_or_default(),
+            dinkassa_machine_key,
+            machine: options.machine.or(profil.machine.clone()).unwrap_or_default(),
+            session_cache: options
+                .session_cache
+                .or_else(|| utils::cache_dir().map(|dir| dir.join(session_fil)))
+                .filter(|_| !options.no_session_cache),
+            bokio_api_url: options.bokio_api_url,
+            bokio_api_token,
+            hemliga_kallor,
+            bokio_company_id,
+            ledger,
+            start_date,
+            end_date,
+            save_files: false,
+            dry_run: false,
+            output_dir: options.output_dir.or(profil.output_dir.clone()),
+            avrundningskonto,
+            kontomappning,
+            retries: options.retries,
+            urval: Urval::Ingen,
+            summary: None,
+            interaktiv: std::io::stdin().is_terminal(),
+            profil: profilnamn.map(|namn| namn.to_string()),
+            tillstand: RefCell::new(tillstand),
+        };
+
+        if login {
+            // Check the password instead of reusing a saved session
+            args.session_cache = None;
+            args.interaktiv = true;
+        }
+
+        if let Command::ImportSie { yes, dry_run, .. } = command {
+            args.dry_run = *dry_run;
+            args.interaktiv = !*yes;
+        }
+
+        if let Command::Import(import) = command {
+            args.urval = if import.yes {
+                Urval::Alla
+            } else if !import.only.is_empty() {
+                Urval::Valda(import.only.clone())
+            } else if import.none {
+                Urval::Ingen
+            } else {
+                Urval::Fraga
+            };
+            args.save_files = import.save_files;
+            args.dry_run = import.dry_run;
+            args.summary = import.summary.clone();
+            args.interaktiv = args.urval == Urval::Fraga;
         }
+
+        args
     }
 
-    if args.dinkassa_username.is_empty() {
-        let username = read_prompt_trim("dinkassa.se username: ");
-        if username.is_empty() {
-            return;
+    /// Key for the imports in the local state. Imports to a ledger are recorded under its
+    /// directory instead of the Bokio company.
+    fn bokforing_id(&self) -> String {
+        match &self.ledger {
+            Some(dir) => std::path::absolute(dir).unwrap_or(dir.clone()).display().to_string(),
+            None => self.bokio_company_id.clone(),
         }
-        args.dinkassa_username = username;
     }
 
-    if args.dinkassa_password.is_empty() {
-        let password = read_password_trim("dinkassa.se password: ");
-        if password.is_empty() {
-            return;
+    /// Reads a secret from the file, command or profile entry it was given with, or
+    /// else from the keyring. Empty when it is not found.
+    fn las_hemlighet(&mut self, namn: &str) -> String {
+        let Some(kalla) = self.hemliga_kallor.remove(namn) else {
+            return String::new();
+        };
+        let resultat = match kalla {
+            Some(secret) => secret.resolve().map(Some),
+            None => keyring_lookup(&keyring_name(self.profil.as_deref(), namn)),
+        };
+        resultat.unwrap_or_else(|err| avsluta(namn, err)).unwrap_or_default()
+    }
+}
+
+/// Asks for missing credentials and logs in to dinkassa.se and Bokio, or opens the
+/// ledger. Returns `None` when a prompt is left empty.
+fn logga_in(args: &mut Cli) -> Option<(DinKassa, Box<dyn AccountingBackend>)> {
+    let kassa = logga_in_dinkassa(args)?;
+    Some((kassa, oppna_bokforing(args)?))
+}
+
+/// Logs in to dinkassa.se and Bokio, or opens the ledger, and runs `kommando`. Returns
+/// its exit code, or 0 when a prompt is left empty.
+fn inloggad(args: &mut Cli, kommando: impl FnOnce(&Cli, &DinKassa, &dyn AccountingBackend) -> i32) -> i32 {
+    match logga_in(args) {
+        Some((kassa, bokforing)) => kommando(args, &kassa, bokforing.as_ref()),
+        None => 0,
+    }
+}
+
+/// The ledger in files when one is configured, otherwise Bokio. Returns `None` when
+/// a prompt for the Bokio credentials is left empty.
+fn oppna_bokforing(args: &mut Cli) -> Option<Box<dyn AccountingBackend>> {
+    if let Some(dir) = &args.ledger {
+        return Some(Box::new(Ledger::new(dir)));
+    }
+    fraga_efter_bokio(args)?;
+    Some(Box::new(skapa_bokio(args)))
+}
+
+/// Asks for missing credentials and logs in to dinkassa.se. Returns `None` when a
+/// prompt is left empty.
+fn logga_in_dinkassa(args: &mut Cli) -> Option<DinKassa> {
+    let interaktiv = args.interaktiv;
+    let machine_login = !args.dinkassa_machine_id.is_empty();
+    if machine_login {
+        if args.dinkassa_integrator_id.is_empty() {
+            if !interaktiv {
+                saknas("DINKASSA_INTEGRATOR_ID");
+            }
+            let integrator_id = read_prompt_trim("dinkassa.se integrator id: ");
+            if integrator_id.is_empty() {
+                return None;
+            }
+            args.dinkassa_integrator_id = integrator_id;
+        }
+
+        if args.dinkassa_machine_key.is_empty() {
+            args.dinkassa_machine_key = args.las_hemlighet("DINKASSA_MACHINE_KEY");
+        }
+        if args.dinkassa_machine_key.is_empty() {
+            if !interaktiv {
+                saknas("DINKASSA_MACHINE_KEY");
+            }
+            let machine_key = read_password_trim("dinkassa.se machine key: ");
+            if machine_key.is_empty() {
+                return None;
+            }
+            args.dinkassa_machine_key = machine_key;
+        }
+    } else {
+        if args.dinkassa_username.is_empty() {
+            if !interaktiv {
+                saknas("DINKASSA_USERNAME");
+            }
+            let username = read_prompt_trim("dinkassa.se username: ");
+            if username.is_empty() {
+                return None;
+            }
+            args.dinkassa_username = username;
+        }
+
+        if args.dinkassa_password.is_empty() {
+            args.dinkassa_password = args.las_hemlighet("DINKASSA_PASSWORD");
+        }
+        let sparad_session = DinKassa::has_saved_session(
+            &args.dinkassa_api_url,
+            &args.dinkassa_username,
+            args.session_cache.as_deref(),
+        );
+        if args.dinkassa_password.is_empty() && !sparad_session {
+            if !interaktiv {
+                saknas("DINKASSA_PASSWORD");
+            }
+            let password = read_password_trim("dinkassa.se password: ");
+            if password.is_empty() {
+                return None;
+            }
+            args.dinkassa_password = password;
         }
-        args.dinkassa_password = password;
     }
 
+    let kassa = if machine_login {
+        DinKassa::login_machine_key(
+            &args.dinkassa_api_url,
+            &args.dinkassa_integrator_id,
+            &args.dinkassa_machine_id,
+            &args.dinkassa_machine_key,
+        )
+    } else {
+        DinKassa::login_username_password(
+            &args.dinkassa_api_url,
+            &args.dinkassa_username,
+            &args.dinkassa_password,
+            args.session_cache.as_deref(),
+        )
+    };
+    let mut kassa = kassa.unwrap_or_else(|err| avsluta("Inloggning på dinkassa.se misslyckades", err));
+    if let Some(retries) = args.retries {
+        kassa.retry.retries = retries;
+    }
+
+    Some(kassa)
+}
+
+/// Asks for the Bokio token and company id if missing. Returns `None` when a prompt
+/// is left empty.
+fn fraga_efter_bokio(args: &mut Cli) -> Option<()> {
+    if args.bokio_api_token.is_empty() {
+        args.bokio_api_token = args.las_hemlighet("BOKIO_API_TOKEN");
+    }
     if args.bokio_api_token.is_empty() {
+        if !args.interaktiv {
+            saknas("BOKIO_API_TOKEN");
+        }
         let token = read_password_trim("Bokio API token: ");
         if token.is_empty() {
-            return;
+            return None;
         }
         args.bokio_api_token = token;
     }
 
     if args.bokio_company_id.is_empty() {
+        if !args.interaktiv {
+            saknas("BOKIO_COMPANY_ID");
+        }
         let company_id = read_prompt_trim("Bokio company id: ");
         if company_id.is_empty() {
-            return;
+            return None;
         }
         args.bokio_company_id = company_id;
     }
+    Some(())
+}
 
-    let kassa = DinKassa::login_username_password(
-        &args.dinkassa_username,
-        &args.dinkassa_password
-    );
-    let kassa = kassa
-        .inspect_err(|err| {
-            eprintln!("Inloggning på dinkassa.se misslyckades: {}", err);
-            std::process::exit(1);
-        })
-        .unwrap();
-
-    let bokio = Bokio::new(
+fn skapa_bokio(args: &Cli) -> Bokio {
+    let mut bokio = Bokio::new(
         &args.bokio_api_url,
         &args.bokio_company_id,
         &args.bokio_api_token,
-    );
+    ).unwrap_or_else(|err| avsluta("Bokio", err));
+    bokio.accounts_cache = utils::cache_dir().map(|dir| dir.join("bokio-accounts.json"));
+    if let Some(retries) = args.retries {
+        bokio.retry.retries = retries;
+    }
+    bokio
+}
 
-    let (mut rapporter, dates) = hamta_rapporter(&args, &kassa, &bokio)
-        .inspect_err(|err| {
-            eprintln!("Kunde inte hämta Z-Rapporter: {}", err);
-            std::process::exit(1);
-        })
-        .unwrap();
-
-    println!(
-        "{} Z-Rapporter för {} ({} - {})",
-        rapporter.len(),
-        kassa.machine.customer_name,
-        format_local_date(&dates.start_date),
-        format_local_date(&dates.end_date),
-    );
+/// The logged in register, or the registers chosen with --machine or in a menu.
+fn valda_kassor(args: &Cli, kassa: &DinKassa) -> Vec<Machine> {
+    if !args.interaktiv && args.machine.is_empty() {
+        vec![kassa.machine.clone()]
+    } else {
+        valj_kassor(kassa, &args.machine).unwrap_or_else(|err| avsluta("Kunde inte välja kassa", err))
+    }
+}
 
-    if !rapporter.is_empty() {
-        let antal_skippade = rakna_importerade_rapporter(&rapporter);
-        importera(&kassa, &bokio, &mut rapporter, args.save_files);
-        let antal_importerade = rakna_importerade_rapporter(&rapporter) - antal_skippade;
+/// Fetches the reports for each chosen register, prints a heading and calls `f`.
+fn for_varje_kassa(
+    args: &Cli,
+    kassa: &DinKassa,
+    bokforing: &dyn AccountingBackend,
+    mut f: impl FnMut(&DinKassa, &mut Vec<RapportImport>),
+) {
+    for machine in valda_kassor(args, kassa) {
+        let kassa = kassa.with_machine(&machine);
+        let (mut rapporter, dates) = hamta_rapporter(args, &kassa, bokforing)
+            .unwrap_or_else(|err| avsluta("Kunde inte hämta Z-Rapporter", err));
 
+        println!(
+            "{} Z-Rapporter för {} {} ({} - {})",
+            rapporter.len(),
+            kassa.machine.customer_name,
+            kassa.machine.name,
+            format_local_date(&dates.start_date),
+            format_local_date(&dates.end_date),
+        );
+
+        f(&kassa, &mut rapporter);
         println!();
+    }
+}
+
+/// Reports values given on the command line that did not match any report.
+fn ej_hittade(val: &[String], hittade: &[String]) -> i32 {
+    let mut exit_code = 0;
+    for part in val.iter().filter(|part| !hittade.contains(part)) {
+        let err = Error::Validation(format!("Z-Rapport {} hittades inte", part));
+        eprintln!("{}", err);
+        exit_code = err.exit_code();
+    }
+    exit_code
+}
+
+fn lista(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend) -> i32 {
+    for_varje_kassa(args, kassa, bokforing, |_, rapporter| {
+        if !rapporter.is_empty() {
+            lista_rapporter(rapporter, args);
+        }
+    });
+    0
+}
+
+fn importera_kassor(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend) -> i32 {
+    let mut antal_importerade = 0;
+    let mut antal_skippade = 0;
+    let mut hittade: Vec<String> = Vec::new();
+    let mut summary = Summary::default();
+    let mut exit_code = 0;
+    for_varje_kassa(args, kassa, bokforing, |kassa, rapporter| {
+        if !rapporter.is_empty() {
+            let skippade = rakna_importerade_rapporter(rapporter);
+            let valda = match &args.urval {
+                Urval::Fraga => None,
+                Urval::Alla => Some(ej_importerade(rapporter)),
+                Urval::Valda(val) => Some(valda_rapporter(rapporter, val, &mut hittade)),
+                Urval::Ingen => Some(Vec::new()),
+            };
+            importera(kassa, bokforing, rapporter, valda, args);
+            antal_importerade += rakna_importerade_rapporter(rapporter) - skippade;
+            antal_skippade += skippade;
+        }
+
+        for import in rapporter.iter() {
+            if let Some(err) = &import.fel
+                && exit_code == 0
+            {
+                exit_code = err.exit_code();
+            }
+            summary.reports.push(ReportSummary::new(&kassa.machine, import));
+        }
+    });
+
+    let antal_forhandsgranskade = summary.reports.iter().filter(|r| r.status == "dry_run").count();
+    if antal_forhandsgranskade > 0 {
+        println!("{} Z-Rapporter förhandsgranskades (--dry-run)", antal_forhandsgranskade);
+    }
+
+    if antal_importerade > 0 || antal_skippade > 0 {
         println!("{} Z-Rapporter importerades", antal_importerade);
         if antal_skippade > 0 {
             println!("{} Z-Rapporter redan importerade", antal_skippade);
         }
     }
+
+    let antal_utan_underlag = summary.reports.iter().filter(|r| r.status == "attachment_missing").count();
+    if antal_utan_underlag > 0 {
+        println!("{} Z-Rapporter saknar underlag, ladda upp det med esbokio resume", antal_utan_underlag);
+    }
+
+    if let Urval::Valda(val) = &args.urval {
+        let code = ej_hittade(val, &hittade);
+        if exit_code == 0 {
+            exit_code = code;
+        }
+    }
+
+    if let Some(path) = &args.summary {
+        summary.skriv(path).unwrap_or_else(|err| avsluta("Kunde inte skriva sammanfattning", err));
+    }
+    exit_code
+}
+
+fn status(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend, summary_path: Option<&Path>) -> i32 {
+    let mut summary = Summary::default();
+    for_varje_kassa(args, kassa, bokforing, |kassa, rapporter| {
+        let ej_bokforda: Vec<String> = rapporter
+            .iter()
+            .filter(|e| e.verifikat.is_none())
+            .map(|e| e.key.to_string())
+            .collect();
+        println!(
+            "{} bokförda, {} ej bokförda",
+            rapporter.len() - ej_bokforda.len(),
+            ej_bokforda.len()
+        );
+        if !ej_bokforda.is_empty() {
+            println!("Ej bokförda: {}", ej_bokforda.join(", "));
+        }
+        for import in rapporter.iter() {
+            summary.reports.push(ReportSummary::new(&kassa.machine, import));
+        }
+    });
+
+    if let Some(path) = summary_path {
+        summary.skriv(path).unwrap_or_else(|err| avsluta("Kunde inte skriva sammanfattning", err));
+    }
+    0
+}
+
+/// Differences between a booked journal entry and the one the Z-report gives
+/// today, compared as the net amount per account.
+fn jamfor_verifikat(forvantat: &NewJournalEntry, bokfort: &JournalEntry, bokforing: &str) -> Vec<String> {
+    let mut konton: Vec<(i32, Decimal, Decimal)> = Vec::new();
+    let mut lagg_till = |account: i32, forvantat: Decimal, bokfort: Decimal| {
+        match konton.iter_mut().find(|k| k.0 == account) {
+            Some(k) => {
+                k.1 += forvantat;
+                k.2 += bokfort;
+            }
+            None => konton.push((account, forvantat, bokfort)),
+        }
+    };
+    for item in forvantat.items.iter() {
+        lagg_till(item.account, item.debit - item.credit, Decimal::ZERO);
+    }
+    for item in bokfort.items.iter() {
+        lagg_till(item.account, Decimal::ZERO, item.debit - item.credit);
+    }
+
+    konton
+        .iter()
+        .filter(|(_, forvantat, bokfort)| forvantat != bokfort)
+        .map(|(account, forvantat, bokfort)| {
+            format!(
+                "konto {}: {} i {}, {} enligt Z-Rapporten",
+                account, money(*bokfort), bokforing, money(*forvantat)
+            )
+        })
+        .collect()
+}
+
+fn verifiera(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend) -> i32 {
+    let mut exit_code = 0;
+    for_varje_kassa(args, kassa, bokforing, |_, rapporter| {
+        for import in rapporter.iter() {
+            let Some(verifikat) = &import.verifikat else {
+                println!("{}: ej bokförd", import.key);
+                continue;
+            };
+
+            let skillnader = match create_journal_entry(&import.sie, &import.konton, args.avrundningskonto) {
+                Ok(forvantat) => jamfor_verifikat(&forvantat, verifikat, &bokforing.name()),
+                Err(err) => vec![err.to_string()],
+            };
+            if skillnader.is_empty() {
+                println!("{}: {} OK", import.key, verifikat.journal_entry_number);
+            } else {
+                println!("{}: {} avviker", import.key, verifikat.journal_entry_number);
+                for skillnad in skillnader {
+                    println!("  {}", skillnad);
+                }
+                exit_code = EXIT_VALIDATION;
+            }
+        }
+    });
+    exit_code
+}
+
+fn vand(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend, val: &[String], utan_fraga: bool) -> i32 {
+    let mut exit_code = 0;
+    let mut hittade: Vec<String> = Vec::new();
+    for_varje_kassa(args, kassa, bokforing, |_, rapporter| {
+        let bokforda: Vec<ZReportKey> = rapporter
+            .iter()
+            .filter(|e| e.verifikat.is_some())
+            .map(|e| e.key.clone())
+            .collect();
+        for part in val {
+            let Some(key) = tolka_val(part, &bokforda) else {
+                continue;
+            };
+            hittade.push(part.clone());
+            let import = rapporter.iter().find(|e| &e.key == key).unwrap();
+            let report_id = import.report.id.clone();
+            let verifikat = import.verifikat.as_ref().unwrap();
+
+            if !utan_fraga && !bekrafta(&format!(
+                "Vänd verifikat {} för Z-Rapport {} (j/N)? ",
+                verifikat.journal_entry_number, key
+            )) {
+                continue;
+            }
+
+            match bokforing.reverse_journal_entry(&verifikat.id) {
+                Ok(vandning) => {
+                    println!(
+                        "Verifikat {} vändes med verifikat {}",
+                        verifikat.journal_entry_number, vandning.journal_entry_number
+                    );
+                    let mut tillstand = args.tillstand.borrow_mut();
+                    if tillstand.remove(&args.bokforing_id(), &report_id).is_some() {
+                        spara_tillstand(&tillstand);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Kunde inte vända verifikat {}: {}", verifikat.journal_entry_number, err);
+                    if exit_code == 0 {
+                        exit_code = err.exit_code();
+                    }
+                }
+            }
+        }
+    });
+
+    let code = ej_hittade(val, &hittade);
+    if exit_code == 0 {
+        exit_code = code;
+    }
+    exit_code
+}
+
+/// Removes recorded imports whose journal entry has been reversed or deleted in Bokio,
+/// and records booked Z-reports in the period that were imported some other way.
+fn stam_av(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend) -> i32 {
+    let mut exit_code = 0;
+    let bokforing_id = args.bokforing_id();
+    let poster: Vec<ImportRecord> = args
+        .tillstand
+        .borrow()
+        .imports
+        .iter()
+        .filter(|p| p.company_id == bokforing_id && !p.journal_entry_id.is_empty())
+        .cloned()
+        .collect();
+    for post in poster {
+        let namn = format!("Z-Rapport K{}:{} ({})", post.register, post.number, post.date);
+        let borttagen = match bokforing.get_journal_entry(&post.journal_entry_id) {
+            Ok(Some(verifikat)) if verifikat.reversed_by_journal_entry_id.is_some() => {
+                println!("{}: verifikat {} är vänt", namn, post.journal_entry_number);
+                true
+            }
+            Ok(Some(verifikat)) => {
+                if verifikat.journal_entry_number != post.journal_entry_number {
+                    println!(
+                        "{}: verifikat {} har nu nummer {}",
+                        namn, post.journal_entry_number, verifikat.journal_entry_number
+                    );
+                    let mut post = post.clone();
+                    post.journal_entry_number = verifikat.journal_entry_number;
+                    args.tillstand.borrow_mut().record(post);
+                }
+                false
+            }
+            Ok(None) => {
+                println!("{}: verifikat {} finns inte i {}", namn, post.journal_entry_number, bokforing.name());
+                true
+            }
+            Err(err) => {
+                eprintln!("Kunde inte hämta verifikat {}: {}", post.journal_entry_number, err);
+                if exit_code == 0 {
+                    exit_code = err.exit_code();
+                }
+                false
+            }
+        };
+        if borttagen {
+            args.tillstand.borrow_mut().remove(&post.company_id, &post.zreport_id);
+        }
+    }
+
+    for_varje_kassa(args, kassa, bokforing, |kassa, rapporter| {
+        for import in rapporter.iter() {
+            let Some(verifikat) = &import.verifikat else {
+                continue;
+            };
+            if args.tillstand.borrow().find(&args.bokforing_id(), &import.report.id).is_some() {
+                continue;
+            }
+            println!("Z-Rapport {}: registreras med verifikat {}", import.key, verifikat.journal_entry_number);
+            let mut post = ImportRecord::new(
+                &args.bokforing_id(),
+                &kassa.machine.id,
+                &import.report,
+                &import.key,
+                &import.sie,
+            );
+            post.set_journal_entry(verifikat);
+            args.tillstand.borrow_mut().record(post);
+        }
+    });
+
+    if let Err(err) = args.tillstand.borrow().save() {
+        avsluta("Kunde inte spara importerade Z-Rapporter", err);
+    }
+    exit_code
+}
+
+/// Finishes imports that were interrupted after the journal entry was created by
+/// uploading the PDF. Imports interrupted before that are forgotten, the Z-reports
+/// are not booked and are imported again as usual.
+fn aterupta(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend) -> i32 {
+    let bokforing_id = args.bokforing_id();
+    let poster: Vec<ImportRecord> = args
+        .tillstand
+        .borrow()
+        .imports
+        .iter()
+        .filter(|p| p.company_id == bokforing_id && p.is_unfinished())
+        .cloned()
+        .collect();
+    if poster.is_empty() {
+        println!("Inga avbrutna importer");
+        return 0;
+    }
+
+    let mut exit_code = 0;
+    for mut post in poster {
+        let namn = format!("Z-Rapport K{}:{} ({})", post.register, post.number, post.date);
+        if !post.done(Step::EntryCreated) {
+            println!("{}: bokfördes inte, importera den igen", namn);
+            let mut tillstand = args.tillstand.borrow_mut();
+            tillstand.remove(&post.company_id, &post.zreport_id);
+            spara_tillstand(&tillstand);
+            continue;
+        }
+
+        println!("{}: laddar upp underlag till verifikat {}...", namn, post.journal_entry_number);
+        if let Err(err) = ladda_upp_underlag(args, kassa, bokforing, &mut post) {
+            eprintln!("Kunde inte slutföra import av {}: {}", namn, err);
+            if exit_code == 0 {
+                exit_code = err.exit_code();
+            }
+        }
+    }
+    exit_code
+}
+
+/// Uploads the PDF for an import, fetching it again if the saved file is gone.
+fn ladda_upp_underlag(
+    args: &Cli,
+    kassa: &DinKassa,
+    bokforing: &dyn AccountingBackend,
+    post: &mut ImportRecord,
+) -> Result<(), Error> {
+    if hamta_registrerat_verifikat(bokforing, post)?.is_none() {
+        println!("* Verifikatet är vänt eller borttaget i {}, Z-Rapporten kan importeras igen", bokforing.name());
+        let mut tillstand = args.tillstand.borrow_mut();
+        tillstand.remove(&post.company_id, &post.zreport_id);
+        spara_tillstand(&tillstand);
+        return Ok(());
+    }
+
+    // A PDF kept from the import is left for the user, one fetched here is removed
+    let (pdf_filename, hamtad) = match post.pdf.clone().filter(|f| std::fs::exists(f).unwrap_or(false)) {
+        Some(filename) => (filename, false),
+        None => {
+            let machine = kassa
+                .machines
+                .iter()
+                .find(|m| m.id == post.machine_id)
+                .ok_or_else(|| Error::Validation(format!("kassan {} finns inte", post.machine_id)))?;
+            let kassa = kassa.with_machine(machine);
+            let filename = post.pdf.clone().unwrap_or_else(|| format!("{}.pdf", kassa.zreport_basename(post.number)));
+            print!("* Hämtar PDF... ");
+            std::io::stdout().flush().ok();
+            let pdf = kassa.zreport_pdf(&post.zreport_id)?;
+            println!("{}", filename);
+            std::fs::write(&filename, pdf).map_err(|e| Error::io(&filename, e))?;
+            (filename, true)
+        }
+    };
+
+    print!("* Laddar upp underlag... ");
+    std::io::stdout().flush().ok();
+    let upload = bokforing.upload(&pdf_filename, "application/pdf", &post.journal_entry_id)?;
+    println!("OK");
+    post.set_uploaded(upload.id);
+    registrera(args, post.clone());
+    if hamtad {
+        ta_bort_filer(&[&pdf_filename]);
+    }
+    Ok(())
+}
+
+/// Looks for booked Z-reports whose journal entry has no attachment in Bokio, and
+/// uploads the PDF from dinkassa.se for them.
+fn kontrollera_underlag(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend, utan_fraga: bool) -> i32 {
+    let mut exit_code = 0;
+    for_varje_kassa(args, kassa, bokforing, |kassa, rapporter| {
+        for import in rapporter.iter() {
+            let Some(verifikat) = &import.verifikat else {
+                continue;
+            };
+            let underlag = match bokforing.list_uploads(&verifikat.id) {
+                Ok(underlag) => underlag,
+                Err(err) => {
+                    eprintln!("Kunde inte hämta underlag för verifikat {}: {}", verifikat.journal_entry_number, err);
+                    if exit_code == 0 {
+                        exit_code = err.exit_code();
+                    }
+                    continue;
+                }
+            };
+            if !underlag.is_empty() {
+                println!("{}: {} OK", import.key, verifikat.journal_entry_number);
+                continue;
+            }
+
+            println!("{}: {} saknar underlag", import.key, verifikat.journal_entry_number);
+            if !utan_fraga && !bekrafta(&format!(
+                "Ladda upp Z-Rapport {} till verifikat {} (j/N)? ",
+                import.key, verifikat.journal_entry_number
+            )) {
+                continue;
+            }
+            if let Err(err) = ladda_upp_zrapport(args, kassa, bokforing, import, verifikat) {
+                eprintln!("Kunde inte ladda upp Z-Rapport {}: {}", import.key, err);
+                if exit_code == 0 {
+                    exit_code = err.exit_code();
+                }
+            }
+        }
+    });
+    exit_code
+}
+
+fn ladda_upp_zrapport(
+    args: &Cli,
+    kassa: &DinKassa,
+    bokforing: &dyn AccountingBackend,
+    import: &RapportImport,
+    verifikat: &JournalEntry,
+) -> Result<(), Error> {
+    let pdf_filename = format!("{}.pdf", kassa.zreport_basename(import.report.number));
+    print!("* Hämtar PDF... ");
+    std::io::stdout().flush().ok();
+    let pdf = kassa.zreport_pdf(&import.report.id)?;
+    println!("{}", pdf_filename);
+    std::fs::write(&pdf_filename, pdf).map_err(|e| Error::io(&pdf_filename, e))?;
+
+    print!("* Laddar upp underlag... ");
+    std::io::stdout().flush().ok();
+    let upload = bokforing.upload(&pdf_filename, "application/pdf", &verifikat.id);
+    ta_bort_filer(&[&pdf_filename]);
+    let upload = upload?;
+    println!("OK");
+
+    let post = args.tillstand.borrow().find(&args.bokforing_id(), &import.report.id).cloned();
+    if let Some(mut post) = post.filter(|p| p.journal_entry_id == verifikat.id) {
+        post.set_uploaded(upload.id);
+        registrera(args, post);
+    }
+    Ok(())
+}
+
+/// A Z-report read from a SIE4 file.
+struct SieImport {
+    fil: PathBuf,
+    /// #FNAMN in the file.
+    foretag: String,
+    key: ZReportKey,
+    sie: SIEReportListItem,
+    verifikat: Option<JournalEntry>,
+}
+
+/// The SIE4 files given, with the files in directories in name order.
+fn sie_filer(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut filer = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            filer.push(path.clone());
+            continue;
+        }
+        let mut katalog: Vec<PathBuf> = std::fs::read_dir(path)
+            .and_then(|entries| entries.map(|e| e.map(|e| e.path())).collect())
+            .map_err(|e| Error::io(path.display(), e))?;
+        katalog.retain(|f| {
+            f.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ["si", "se", "sie"].contains(&ext.to_lowercase().as_str()))
+        });
+        katalog.sort();
+        filer.extend(katalog);
+    }
+    Ok(filer)
+}
+
+/// Reads the Z-reports in SIE4 files and looks them up in Bokio like [`hamta_rapporter`].
+fn las_sie_filer(args: &Cli, bokforing: &dyn AccountingBackend, paths: &[PathBuf]) -> Result<Vec<SieImport>, Error> {
+    let mut rapporter: Vec<SieImport> = Vec::new();
+    for fil in sie_filer(paths)? {
+        let data = std::fs::read(&fil).map_err(|e| Error::io(fil.display(), e))?;
+        let sie4 = sie::parse(&data).map_err(|e| match e {
+            Error::Parse(msg) => Error::Parse(format!("{}, {}", fil.display(), msg)),
+            e => e,
+        })?;
+        if sie4.checksum_ok == Some(false) {
+            eprintln!("Varning: Kontrollsumman (#KSUMMA) i {} stämmer inte", fil.display());
+        }
+        for sie in sie4.zreports() {
+            let Ok(key) = sie.key() else {
+                eprintln!("Varning: Kunde inte tolka Z-Rapport {} i {}, hoppar över", sie.zreport, fil.display());
+                continue;
+            };
+            // The same report may be in several files, e.g. both from export and export-sie
+            if let Some(tidigare) = rapporter.iter().find(|r| r.key == key) {
+                if kontosummor(&tidigare.sie) != kontosummor(&sie) {
+                    return Err(Error::Validation(format!(
+                        "Z-Rapport {} har olika belopp i {} och {}",
+                        key,
+                        tidigare.fil.display(),
+                        fil.display()
+                    )));
+                }
+                eprintln!(
+                    "Varning: Z-Rapport {} finns även i {}, hoppar över den i {}",
+                    key,
+                    tidigare.fil.display(),
+                    fil.display()
+                );
+                continue;
+            }
+            rapporter.push(SieImport {
+                fil: fil.clone(),
+                foretag: sie4.company_name.clone().unwrap_or_default(),
+                key,
+                sie,
+                verifikat: None,
+            });
+        }
+    }
+
+    let datum: Vec<NaiveDate> = rapporter.iter().filter_map(|r| r.key.date.parse().ok()).collect();
+    let (Some(start), Some(end)) = (datum.iter().min(), datum.iter().max()) else {
+        return Ok(rapporter);
+    };
+    let journal = bokforing.list_journal(start.checked_sub_days(Days::new(14)), Some(*end))?;
+    for rapport in rapporter.iter_mut() {
+        let title = rapport.sie.verifikatnamn().to_lowercase();
+        rapport.verifikat = journal
+            .iter()
+            .find(|e| e.title.to_lowercase() == title && e.reversed_by_journal_entry_id.is_none())
+            .cloned();
+        if rapport.verifikat.is_none()
+            && let Some(post) = args.tillstand.borrow().find_key(&args.bokforing_id(), &rapport.key)
+            && !post.journal_entry_id.is_empty()
+        {
+            rapport.verifikat = hamta_registrerat_verifikat(bokforing, post)?;
+        }
+    }
+    Ok(rapporter)
+}
+
+/// The amount per account of a Z-report, without accounts summing to zero.
+fn kontosummor(rapport: &SIEReportListItem) -> BTreeMap<&str, Decimal> {
+    let mut summor: BTreeMap<&str, Decimal> = BTreeMap::new();
+    for account in rapport.accounts.iter() {
+        *summor.entry(account.number.as_str()).or_default() += account.amount;
+    }
+    summor.retain(|_, belopp| !belopp.is_zero());
+    summor
+}
+
+/// Imports Z-reports from SIE4 files, e.g. saved with export, with the PDF next to
+/// the file as attachment. Nothing is recorded in the local state, since the id of
+/// the report in dinkassa.se is not known.
+fn importera_sie(args: &Cli, bokforing: &dyn AccountingBackend, paths: &[PathBuf], utan_fraga: bool) -> i32 {
+    let rapporter = las_sie_filer(args, bokforing, paths)
+        .unwrap_or_else(|err| avsluta("Kunde inte importera SIE4-filer", err));
+    println!("{} Z-Rapporter i SIE4-filer", rapporter.len());
+    for rapport in rapporter.iter() {
+        match &rapport.verifikat {
+            Some(verifikat) => println!("{}: bokförd som {}", rapport.key, verifikat.journal_entry_number),
+            None => println!("{}: ej bokförd ({})", rapport.key, rapport.fil.display()),
+        }
+    }
+
+    let valda: Vec<&SieImport> = rapporter.iter().filter(|r| r.verifikat.is_none()).collect();
+    if valda.is_empty() {
+        println!("Inga Z-Rapporter att importera");
+        return 0;
+    }
+    if !utan_fraga && !args.dry_run && !bekrafta(&format!("Importera {} Z-Rapporter (j/N)? ", valda.len())) {
+        return 0;
+    }
+
+    let mut exit_code = 0;
+    let mut importerade = 0;
+    for rapport in valda {
+        println!();
+        match importera_sie_rapport(args, bokforing, rapport) {
+            Ok(true) => importerade += 1,
+            Ok(false) => {}
+            Err(err) => {
+                eprintln!("Kunde inte importera Z-Rapport {}: {}", rapport.key, err);
+                exit_code = err.exit_code();
+                if !fortsatt_efter(&err) {
+                    break;
+                }
+            }
+        }
+    }
+    if !args.dry_run {
+        println!();
+        println!("{} Z-Rapporter importerades", importerade);
+    }
+    exit_code
+}
+
+/// Books a Z-report read from a SIE4 file. Returns false with --dry-run.
+fn importera_sie_rapport(args: &Cli, bokforing: &dyn AccountingBackend, rapport: &SieImport) -> Result<bool, Error> {
+    println!("Importerar Z-Rapport {} från {}...", rapport.key, rapport.fil.display());
+    let konton = args.kontomappning.apply(&args.bokio_company_id, rapport.key.register, &rapport.sie.accounts);
+    let mut verifikat = create_journal_entry(&rapport.sie, &konton, args.avrundningskonto)?;
+    verifikat.company_name = rapport.foretag.clone();
+    kontrollera_konton(bokforing, &verifikat, &konton)?;
+    let pdf_filename = rapport.fil.with_extension("pdf");
+    if args.dry_run {
+        visa_verifikat(&konton, &verifikat, args.avrundningskonto);
+        if pdf_filename.exists() {
+            println!("Underlag: {} (application/pdf)", pdf_filename.display());
+        }
+        return Ok(false);
+    }
+
+    print!("* Bokför Z-Rapport {}... ", rapport.key);
+    std::io::stdout().flush().ok();
+    let verifikat = bokforing.create_journal_entry(&verifikat)?;
+    println!("{}", verifikat.journal_entry_number);
+
+    if !pdf_filename.exists() {
+        println!("* Inget underlag, {} finns inte", pdf_filename.display());
+        return Ok(true);
+    }
+    print!("* Laddar upp underlag... ");
+    std::io::stdout().flush().ok();
+    match bokforing.upload(&pdf_filename.display().to_string(), "application/pdf", &verifikat.id) {
+        Ok(_) => println!("OK"),
+        Err(e) => {
+            eprintln!("Misslyckades: {}", e);
+            eprintln!("Ladda upp underlaget senare med esbokio verify-attachments");
+        }
+    }
+    Ok(true)
+}
+
+/// Saves the journal entries for the Z-reports in one SIE4 file, with account mapping
+/// and rounding as when importing. Neither Bokio nor the local state is used.
+fn exportera_sie(args: &Cli, kassa: &DinKassa, output: Option<&Path>) -> i32 {
+    let interval = DateRange::new(&args.start_date, &args.end_date);
+    let fil = output.map(Path::to_path_buf).unwrap_or_else(|| {
+        let namn = format!(
+            "zrapporter-{}-{}.se",
+            format_local_date(&interval.start_date),
+            format_local_date(&interval.end_date)
+        );
+        args.output_dir.as_deref().unwrap_or(Path::new(".")).join(namn)
+    });
+    let mut sie4 = Sie {
+        program: Some("esbokio".to_string()),
+        program_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        sie_type: Some(4),
+        generated: Some(chrono::Local::now().date_naive()),
+        company_name: Some(kassa.machine.customer_name.clone()),
+        ..Sie::default()
+    };
+
+    let mut exit_code = 0;
+    for machine in valda_kassor(args, kassa) {
+        let kassa = kassa.with_machine(&machine);
+        let (rapporter, _) = hamta_zrapporter(args, &kassa)
+            .unwrap_or_else(|err| avsluta("Kunde inte hämta Z-Rapporter", err));
+        for import in rapporter.iter() {
+            let verifikat = create_journal_entry(&import.sie, &import.konton, args.avrundningskonto)
+                .and_then(|verifikat| {
+                    let datum = verifikat.date.parse::<NaiveDate>()
+                        .map_err(|e| Error::Parse(format!("datum {}: {}", verifikat.date, e)))?;
+                    Ok((verifikat, datum))
+                });
+            let (verifikat, datum) = match verifikat {
+                Ok(verifikat) => verifikat,
+                Err(err) => {
+                    eprintln!("Kunde inte exportera Z-Rapport {}: {}", import.key, err);
+                    exit_code = err.exit_code();
+                    continue;
+                }
+            };
+            println!("Z-Rapport {}: {}", import.key, verifikat.title);
+            for konto in import.konton.iter() {
+                if !sie4.accounts.iter().any(|a| a.number == konto.number) {
+                    sie4.accounts.push(sie::Account { number: konto.number.clone(), name: konto.description.clone() });
+                }
+            }
+            sie4.vouchers.push(sie::Voucher {
+                series: String::new(),
+                number: String::new(),
+                date: datum,
+                text: verifikat.title,
+                transactions: verifikat
+                    .items
+                    .iter()
+                    .map(|item| sie::Transaction {
+                        account: item.account.to_string(),
+                        objects: Vec::new(),
+                        amount: item.debit - item.credit,
+                    })
+                    .collect(),
+            });
+        }
+    }
+    sie4.accounts.sort_by(|a, b| a.number.cmp(&b.number));
+
+    if let Some(dir) = fil.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|e| avsluta("Kunde inte skapa katalog", Error::io(dir.display(), e)));
+    }
+    std::fs::write(&fil, sie::write(&sie4))
+        .unwrap_or_else(|e| avsluta("Kunde inte spara SIE4-filen", Error::io(fil.display(), e)));
+    println!("{} verifikat sparades i {}", sie4.vouchers.len(), fil.display());
+    exit_code
+}
+
+fn bekrafta(fraga: &str) -> bool {
+    print!("{}", fraga);
+    std::io::stdout().flush().unwrap();
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).is_ok() && matches!(input.trim().to_lowercase().as_str(), "j" | "y")
+}
+
+/// Saves the PDF, SIE4, JSON and journal entry preview for a Z-report in `dir`.
+fn exportera_rapport(kassa: &DinKassa, import: &RapportImport, args: &Cli, dir: &Path) -> Result<(), Error> {
+    let basename = kassa.zreport_basename(import.report.number);
+    let spara = |filnamn: String, data: &[u8]| -> Result<(), Error> {
+        let path = dir.join(filnamn);
+        std::fs::write(&path, data).map_err(|e| Error::io(path.display(), e))?;
+        println!("* {}", path.display());
+        Ok(())
+    };
+
+    spara(format!("{}.pdf", basename), &kassa.zreport_pdf(&import.report.id)?)?;
+    spara(format!("{}.si", basename), &kassa.zreport_sie(&import.report.id)?)?;
+    spara(format!("{}.json", basename), &serde_json::to_vec_pretty(&import.sie)?)?;
+    let journal_entry = create_journal_entry(&import.sie, &import.konton, args.avrundningskonto)?;
+    spara(format!("{}.bokio.json", basename), &serde_json::to_vec_pretty(&CreateJournal::from(&journal_entry))?)?;
+    Ok(())
+}
+
+fn exportera(args: &Cli, kassa: &DinKassa, bokforing: &dyn AccountingBackend, dir: &Path) -> i32 {
+    std::fs::create_dir_all(dir).unwrap_or_else(|e| avsluta("Kunde inte skapa katalog", Error::io(dir.display(), e)));
+    let mut exit_code = 0;
+    for_varje_kassa(args, kassa, bokforing, |kassa, rapporter| {
+        for import in rapporter.iter() {
+            println!("Z-Rapport {}", import.key);
+            if let Err(err) = exportera_rapport(kassa, import, args, dir) {
+                eprintln!("Kunde inte exportera Z-Rapport {}: {}", import.key, err);
+                if exit_code == 0 {
+                    exit_code = err.exit_code();
+                }
+            }
+        }
+    });
+    exit_code
+}
+
+/// Checks the Bokio token and saves the secrets entered in the keyring. The password
+/// for dinkassa.se has already been checked when logging in.
+fn spara_inloggning(args: &Cli, bokforing: &dyn AccountingBackend) -> i32 {
+    if let Err(err) = bokforing.chart_of_accounts(true) {
+        eprintln!("Inloggning i {} misslyckades: {}", bokforing.name(), err);
+        return err.exit_code();
+    }
+
+    let hemligheter = [
+        ("DINKASSA_PASSWORD", &args.dinkassa_password),
+        ("DINKASSA_MACHINE_KEY", &args.dinkassa_machine_key),
+        ("BOKIO_API_TOKEN", &args.bokio_api_token),
+    ];
+    for (namn, value) in hemligheter.into_iter().filter(|(_, value)| !value.is_empty()) {
+        let nyckel = keyring_name(args.profil.as_deref(), namn);
+        if let Err(err) = keyring_store(&nyckel, value) {
+            eprintln!("Kunde inte spara {} i nyckelringen: {}", namn, err);
+            return err.exit_code();
+        }
+        println!("{} sparades i nyckelringen som {}", namn, nyckel);
+    }
+    0
+}
+
+fn main() {
+    cli::remove_empty_env_vars();
+    let options = Options::try_parse().and_then(Options::check).unwrap_or_else(|err| {
+        // --help and --version are "errors" printed to stdout
+        let exit_code = if err.use_stderr() { 1 } else { 0 };
+        err.print().ok();
+        std::process::exit(exit_code);
+    });
+    let command = options.command.unwrap_or(Command::Import(options.import));
+    let mut args = Cli::new(options.global, &command);
+
+    let exit_code = match &command {
+        Command::List => inloggad(&mut args, lista),
+        Command::Import(_) => inloggad(&mut args, importera_kassor),
+        // SIE4 files are imported without dinkassa.se
+        Command::ImportSie { paths, yes, .. } => match oppna_bokforing(&mut args) {
+            Some(bokforing) => importera_sie(&args, bokforing.as_ref(), paths, *yes),
+            None => 0,
+        },
+        // The SIE4 file is the way to book when Bokio cannot be used
+        Command::ExportSie { output } => match logga_in_dinkassa(&mut args) {
+            Some(kassa) => exportera_sie(&args, &kassa, output.as_deref()),
+            None => 0,
+        },
+        Command::Status { summary } => {
+            inloggad(&mut args, |args, kassa, bokforing| status(args, kassa, bokforing, summary.as_deref()))
+        }
+        Command::Verify => inloggad(&mut args, verifiera),
+        Command::Reverse { reports, yes } => {
+            inloggad(&mut args, |args, kassa, bokforing| vand(args, kassa, bokforing, reports, *yes))
+        }
+        Command::Reconcile => inloggad(&mut args, stam_av),
+        Command::Resume => inloggad(&mut args, aterupta),
+        Command::VerifyAttachments { yes } => {
+            inloggad(&mut args, |args, kassa, bokforing| kontrollera_underlag(args, kassa, bokforing, *yes))
+        }
+        Command::Login => inloggad(&mut args, |args, _, bokforing| spara_inloggning(args, bokforing)),
+        Command::Export { output } => inloggad(&mut args, |args, kassa, bokforing| {
+            let dir = output.clone().or(args.output_dir.clone()).unwrap_or_else(|| PathBuf::from("."));
+            exportera(args, kassa, bokforing, &dir)
+        }),
+    };
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
 }
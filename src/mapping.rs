@@ -0,0 +1,231 @@
+use crate::error::Error;
+use crate::eskassa::SIEReportAccount;
+use crate::utils::tolka_konto;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Rules for booking ES Kassa accounts on other accounts in Bokio, read from a TOML file:
+///
+/// ```toml
+/// [[rule]]
+/// from = "1580"
+/// to = "1581"
+///
+/// [[rule]]
+/// from = ["1930", "1931"]
+/// to = "1930"
+/// register = 2
+///
+/// [[rule]]
+/// from = "3001"
+/// company = "company-1"
+/// split = [{ to = "3001", percent = 80 }, { to = "3002", percent = 20 }]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct AccountMap {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub from: Accounts,
+    pub to: Option<String>,
+    #[serde(default)]
+    pub split: Vec<Split>,
+    /// Only for this Bokio company.
+    pub company: Option<String>,
+    /// Only for this register (K1, K2...).
+    pub register: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Accounts {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Split {
+    pub to: String,
+    pub percent: Decimal,
+}
+
+/// An account line after mapping.
+#[derive(Clone, Debug)]
+pub struct MappedAccount {
+    pub number: String,
+    pub description: String,
+    pub amount: Decimal,
+    /// ES Kassa accounts booked on this line.
+    pub sources: Vec<String>,
+}
+
+impl MappedAccount {
+    pub fn is_mapped(&self) -> bool {
+        self.sources.len() != 1 || self.sources[0] != self.number
+    }
+}
+
+impl Accounts {
+    fn contains(&self, account: &str) -> bool {
+        match self {
+            Accounts::One(a) => a == account,
+            Accounts::Many(a) => a.iter().any(|a| a == account),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &String> {
+        match self {
+            Accounts::One(a) => std::slice::from_ref(a).iter(),
+            Accounts::Many(a) => a.iter(),
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, company: &str, register: u32, account: &str) -> bool {
+        self.from.contains(account)
+            && self.company.as_ref().is_none_or(|c| c == company)
+            && self.register.is_none_or(|r| r == register)
+    }
+
+    /// Rules for a register are more specific than rules for a company.
+    fn specificity(&self) -> u32 {
+        self.register.map_or(0, |_| 2) + self.company.as_ref().map_or(0, |_| 1)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for account in self.from.iter().chain(self.to.iter()).chain(self.split.iter().map(|s| &s.to)) {
+            if tolka_konto(account).is_none() {
+                return Err(format!("ogiltigt kontonummer \"{}\"", account));
+            }
+        }
+        if self.from.iter().next().is_none() {
+            return Err("from saknas".to_string());
+        }
+        match (&self.to, self.split.is_empty()) {
+            (Some(_), false) => return Err("ange antingen to eller split".to_string()),
+            (None, true) => return Err("to eller split saknas".to_string()),
+            _ => {}
+        }
+        if !self.split.is_empty() {
+            let total: Decimal = self.split.iter().map(|s| s.percent).sum();
+            if total != Decimal::ONE_HUNDRED || self.split.iter().any(|s| s.percent <= Decimal::ZERO) {
+                return Err(format!("split måste summera till 100 procent, inte {}", total));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let from: Vec<&str> = self.from.iter().map(|a| a.as_str()).collect();
+        write!(f, "{} → ", from.join(" + "))?;
+        if let Some(to) = &self.to {
+            return write!(f, "{}", to);
+        }
+        let split: Vec<String> = self.split.iter().map(|s| format!("{} {} %", s.to, s.percent)).collect();
+        write!(f, "{}", split.join(", "))
+    }
+}
+
+impl AccountMap {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::io(path.display(), e))?;
+        let map: AccountMap = toml::from_str(&text)
+            .map_err(|e| Error::Parse(format!("kontomappning {}: {}", path.display(), e)))?;
+        for (i, rule) in map.rules.iter().enumerate() {
+            rule.validate().map_err(|msg| {
+                Error::Validation(format!("Kontomappning {}, regel {}: {}", path.display(), i + 1, msg))
+            })?;
+        }
+        Ok(map)
+    }
+
+    fn rule(&self, company: &str, register: u32, account: &str) -> Option<&Rule> {
+        // max_by_key returns the last of equal elements, the first rule in the file should win
+        self.rules
+            .iter()
+            .rev()
+            .filter(|r| r.matches(company, register, account))
+            .max_by_key(|r| r.specificity())
+    }
+
+    /// Rules used for any of `accounts`, in file order.
+    pub fn effective<'a>(
+        &self,
+        company: &str,
+        register: u32,
+        accounts: impl IntoIterator<Item = &'a SIEReportAccount>,
+    ) -> Vec<&Rule> {
+        let mut used: Vec<&Rule> = Vec::new();
+        for acc in accounts {
+            if let Some(rule) = self.rule(company, register, &acc.number)
+                && !used.iter().any(|r| std::ptr::eq(*r, rule))
+            {
+                used.push(rule);
+            }
+        }
+        used.sort_by_key(|r| self.rules.iter().position(|x| std::ptr::eq(x, *r)));
+        used
+    }
+
+    /// Rewrites, splits and merges accounts. Lines for the same account are merged
+    /// and merged lines that net to zero are dropped.
+    pub fn apply(&self, company: &str, register: u32, accounts: &[SIEReportAccount]) -> Vec<MappedAccount> {
+        let mut result: Vec<MappedAccount> = Vec::new();
+        for acc in accounts {
+            let parts: Vec<(String, Decimal)> = match self.rule(company, register, &acc.number) {
+                None => vec![(acc.number.clone(), acc.amount)],
+                Some(Rule { to: Some(to), .. }) => vec![(to.clone(), acc.amount)],
+                Some(rule) => split(acc.amount, &rule.split),
+            };
+
+            for (number, amount) in parts {
+                match result.iter_mut().find(|m| m.number == number) {
+                    Some(m) => {
+                        m.amount += amount;
+                        if !m.sources.contains(&acc.number) {
+                            m.sources.push(acc.number.clone());
+                        }
+                        if number == acc.number {
+                            m.description = acc.description.clone();
+                        }
+                    }
+                    None => result.push(MappedAccount {
+                        number,
+                        description: acc.description.clone(),
+                        amount,
+                        sources: vec![acc.number.clone()],
+                    }),
+                }
+            }
+        }
+
+        result.retain(|m| !(m.is_mapped() && m.amount.is_zero()));
+        result
+    }
+}
+
+/// Splits `amount` in whole öre, the last part gets what is left.
+fn split(amount: Decimal, parts: &[Split]) -> Vec<(String, Decimal)> {
+    let mut left = amount;
+    let mut result = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        let share = if i + 1 == parts.len() {
+            left
+        } else {
+            (amount * part.percent / Decimal::ONE_HUNDRED).round_dp(2)
+        };
+        left -= share;
+        result.push((part.to.clone(), share));
+    }
+    result
+}
@@ -0,0 +1,53 @@
+use crate::error::Error;
+use std::time::Duration;
+
+/// Retries transient failures with exponential backoff. A `Retry-After` from the
+/// server (e.g. on 429) is used instead of the computed delay.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1-based) when the server gives no hint.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Runs `op` until it succeeds, fails with a permanent error or the retries are used up.
+    /// Only use this for idempotent operations.
+    pub fn run<T>(&self, mut op: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut retry = 0;
+        loop {
+            match op() {
+                Err(err) if retry < self.retries && err.is_transient() => {
+                    retry += 1;
+                    let delay = err.retry_after().unwrap_or_else(|| self.backoff(retry)).min(self.max_delay);
+                    eprintln!(
+                        "{}. Försöker igen om {} s ({} av {})",
+                        err,
+                        delay.as_secs_f32(),
+                        retry,
+                        self.retries
+                    );
+                    std::thread::sleep(delay);
+                }
+                res => return res,
+            }
+        }
+    }
+}
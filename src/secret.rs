@@ -0,0 +1,125 @@
+use crate::error::Error;
+use serde::Deserialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Service attribute for entries in the keyring.
+const KEYRING_SERVICE: &str = "esbokio";
+
+/// Where a password or token is read from, so that it does not have to be written in
+/// scripts or config files. In a profile this is e.g. `{ command = "pass show dinkassa" }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Secret {
+    /// An environment variable.
+    Env(String),
+    /// The first line of a file, e.g. a Docker or systemd credential.
+    File(PathBuf),
+    /// The first line printed by a shell command.
+    Command(String),
+    /// An entry in the keyring, see [`keyring_lookup`].
+    Keyring(String),
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String, Error> {
+        let value = match self {
+            Secret::Env(name) => std::env::var(name)
+                .ok()
+                .ok_or_else(|| Error::Auth(format!("miljövariabeln {} saknas", name)))?,
+            Secret::File(path) => std::fs::read_to_string(path).map_err(|e| Error::io(path.display(), e))?,
+            Secret::Command(command) => run(command)?,
+            Secret::Keyring(name) => keyring_lookup(name)?
+                .ok_or_else(|| Error::Auth(format!("{} finns inte i nyckelringen", name)))?,
+        };
+
+        let value = value.lines().next().unwrap_or_default().trim_end().to_string();
+        if value.is_empty() {
+            return Err(Error::Auth(format!("inget värde från {}", self)));
+        }
+        Ok(value)
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secret::Env(name) => write!(f, "miljövariabeln {}", name),
+            Secret::File(path) => write!(f, "filen {}", path.display()),
+            Secret::Command(command) => write!(f, "kommandot \"{}\"", command),
+            Secret::Keyring(name) => write!(f, "{} i nyckelringen", name),
+        }
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+fn run(command: &str) -> Result<String, Error> {
+    let output = shell(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::io(command, e))?;
+    if !output.status.success() {
+        return Err(Error::Auth(format!("kommandot \"{}\" misslyckades ({})", command, output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Looks up a secret in the keyring (Secret Service on Linux) with `secret-tool`
+/// from libsecret. Returns `None` when the entry or `secret-tool` is missing.
+pub fn keyring_lookup(name: &str) -> Result<Option<String>, Error> {
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", KEYRING_SERVICE, "name", name])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            let value = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+            Ok(Some(value).filter(|v| !v.is_empty()))
+        }
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io("secret-tool", e)),
+    }
+}
+
+/// Name of the keyring entry for an environment variable, e.g. "butik/BOKIO_API_TOKEN".
+pub fn keyring_name(profile: Option<&str>, variable: &str) -> String {
+    match profile {
+        Some(profile) => format!("{}/{}", profile, variable),
+        None => variable.to_string(),
+    }
+}
+
+/// Saves a secret in the keyring with `secret-tool`.
+pub fn keyring_store(name: &str, value: &str) -> Result<(), Error> {
+    let mut child = Command::new("secret-tool")
+        .args(["store", "--label", &format!("esbokio {}", name), "service", KEYRING_SERVICE, "name", name])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::io("secret-tool", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(value.as_bytes())
+        .map_err(|e| Error::io("secret-tool", e))?;
+    let status = child.wait().map_err(|e| Error::io("secret-tool", e))?;
+    if !status.success() {
+        return Err(Error::Auth(format!("secret-tool kunde inte spara {} ({})", name, status)));
+    }
+    Ok(())
+}
@@ -0,0 +1,508 @@
+//! Reader for SIE4 files, the format dinkassa.se exports Z-reports in besides JSON.
+//! See the SIE 4B specification from SIE-Gruppen.
+
+use crate::error::Error;
+use crate::eskassa::{SIEReportAccount, SIEReportListItem};
+use chrono::NaiveDate;
+use crc32fast::Hasher;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// Characters 0x80-0xFF of code page 437, which SIE calls PC8.
+const PC8: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[derive(Debug, Default)]
+pub struct Sie {
+    /// #FLAGGA, 1 once the file has been imported by the receiver.
+    pub flag: u8,
+    pub sie_type: Option<u32>,
+    pub program: Option<String>,
+    pub program_version: Option<String>,
+    pub generated: Option<NaiveDate>,
+    pub company_name: Option<String>,
+    pub accounts: Vec<Account>,
+    pub dimensions: Vec<Dimension>,
+    pub objects: Vec<Object>,
+    pub vouchers: Vec<Voucher>,
+    /// Whether #KSUMMA matched the contents, `None` for files without it.
+    pub checksum_ok: Option<bool>,
+}
+
+/// #KONTO
+#[derive(Debug)]
+pub struct Account {
+    pub number: String,
+    pub name: String,
+}
+
+/// #DIM, e.g. 1 for cost centre.
+#[derive(Debug)]
+pub struct Dimension {
+    pub number: u32,
+    pub name: String,
+}
+
+/// #OBJEKT
+#[derive(Debug)]
+pub struct Object {
+    pub dimension: u32,
+    pub number: String,
+    pub name: String,
+}
+
+/// #VER with its #TRANS rows.
+#[derive(Debug)]
+pub struct Voucher {
+    pub series: String,
+    pub number: String,
+    pub date: NaiveDate,
+    pub text: String,
+    pub transactions: Vec<Transaction>,
+}
+
+/// #TRANS. The date, text and quantity of the row are not read.
+#[derive(Debug)]
+pub struct Transaction {
+    pub account: String,
+    /// Dimension and object number pairs.
+    pub objects: Vec<(u32, String)>,
+    pub amount: Decimal,
+}
+
+impl Sie {
+    /// The sum of the #TRANS rows per account over all vouchers.
+    pub fn account_totals(&self) -> BTreeMap<String, Decimal> {
+        let mut totals: BTreeMap<String, Decimal> = BTreeMap::new();
+        for transaction in self.vouchers.iter().flat_map(|v| v.transactions.iter()) {
+            *totals.entry(transaction.account.clone()).or_default() += transaction.amount;
+        }
+        totals
+    }
+
+    /// The vouchers as Z-reports like those in the JSON listing from dinkassa.se,
+    /// where the voucher text is the report, e.g. "K1:12", or the journal entry title
+    /// written by [`write`], e.g. "Kassa 1, Z-Rapport #12". Rows for the same account
+    /// are added together.
+    pub fn zreports(&self) -> Vec<SIEReportListItem> {
+        self.vouchers
+            .iter()
+            .map(|voucher| {
+                let date = voucher.date.format("%Y-%m-%dT00:00:00").to_string();
+                let mut accounts: Vec<SIEReportAccount> = Vec::new();
+                for transaction in voucher.transactions.iter() {
+                    match accounts.iter_mut().find(|a| a.number == transaction.account) {
+                        Some(account) => account.amount += transaction.amount,
+                        None => accounts.push(SIEReportAccount {
+                            description: self
+                                .accounts
+                                .iter()
+                                .find(|a| a.number == transaction.account)
+                                .map(|a| a.name.clone())
+                                .unwrap_or_default(),
+                            number: transaction.account.clone(),
+                            amount: transaction.amount,
+                        }),
+                    }
+                }
+                let zreport = SIEReportListItem::zreport_from_verifikatnamn(&voucher.text)
+                    .unwrap_or_else(|| voucher.text.clone());
+                SIEReportListItem {
+                    zreport,
+                    report_date_time: date.clone(),
+                    first_transaction_date_time: date.clone(),
+                    last_transaction_date_time: date,
+                    accounts,
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn decode_pc8(data: &[u8]) -> String {
+    data.iter()
+        .map(|&b| if b < 0x80 { b as char } else { PC8[(b - 0x80) as usize] })
+        .collect()
+}
+
+/// Characters that PC8 lacks are written as "?".
+pub fn encode_pc8(s: &str) -> Vec<u8> {
+    s.chars()
+        .map(|c| match c {
+            c if c.is_ascii() => c as u8,
+            c => PC8.iter().position(|&p| p == c).map_or(b'?', |i| 0x80 + i as u8),
+        })
+        .collect()
+}
+
+enum Token {
+    Field(String),
+    /// An object list in braces.
+    List(Vec<String>),
+}
+
+/// The fields of a line after the label.
+struct Fields(Vec<Token>);
+
+impl Fields {
+    fn get(&self, i: usize) -> Option<&str> {
+        match self.0.get(i) {
+            Some(Token::Field(value)) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    fn required(&self, i: usize, name: &str) -> Result<&str, String> {
+        self.get(i).ok_or_else(|| format!("{} saknas", name))
+    }
+
+    /// A field that may be left out or given as "".
+    fn optional(&self, i: usize) -> Option<&str> {
+        self.get(i).filter(|value| !value.is_empty())
+    }
+
+    fn list(&self, i: usize, name: &str) -> Result<&[String], String> {
+        match self.0.get(i) {
+            Some(Token::List(list)) => Ok(list),
+            _ => Err(format!("{} saknas", name)),
+        }
+    }
+}
+
+/// Reads a SIE4 file. Labels this reader does not need are skipped, as the
+/// specification asks of readers.
+pub fn parse(data: &[u8]) -> Result<Sie, Error> {
+    let text = decode_pc8(data);
+    let mut reader = Reader::default();
+    for (i, line) in text.lines().enumerate() {
+        reader
+            .line(line.trim())
+            .map_err(|msg| Error::Parse(format!("SIE4 rad {}: {}", i + 1, msg)))?;
+    }
+    if reader.voucher.is_some() {
+        return Err(Error::Parse("SIE4: verifikationen avslutas inte med }".to_string()));
+    }
+    Ok(reader.sie)
+}
+
+#[derive(Default)]
+struct Reader {
+    sie: Sie,
+    voucher: Option<Voucher>,
+    in_block: bool,
+    /// Started by the first #KSUMMA.
+    checksum: Option<Hasher>,
+}
+
+impl Reader {
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        match line {
+            "" => return Ok(()),
+            "{" if self.voucher.is_some() && !self.in_block => {
+                self.in_block = true;
+                return Ok(());
+            }
+            "}" if self.in_block => {
+                self.sie.vouchers.extend(self.voucher.take());
+                self.in_block = false;
+                return Ok(());
+            }
+            "{" | "}" => return Err(format!("oväntad {}", line)),
+            _ => {}
+        }
+        if self.voucher.is_some() && !self.in_block {
+            return Err("{ saknas efter #VER".to_string());
+        }
+
+        let mut tokens = tokenize(line)?;
+        let label = match tokens.first() {
+            Some(Token::Field(label)) if label.starts_with('#') => label.to_uppercase(),
+            _ => return Err(format!("rad utan etikett: {}", line)),
+        };
+        tokens.remove(0);
+        let fields = Fields(tokens);
+
+        if label == "#KSUMMA" {
+            return self.checksum(&fields);
+        }
+        if let Some(checksum) = self.checksum.as_mut() {
+            checksum.update(&encode_pc8(&label));
+            for token in fields.0.iter() {
+                match token {
+                    Token::Field(value) => checksum.update(&encode_pc8(value)),
+                    Token::List(list) => list.iter().for_each(|value| checksum.update(&encode_pc8(value))),
+                }
+            }
+        }
+
+        if self.in_block {
+            let voucher = self.voucher.as_mut().unwrap();
+            return match label.as_str() {
+                "#TRANS" => {
+                    voucher.transactions.push(parse_trans(&fields)?);
+                    Ok(())
+                }
+                // #RTRANS is followed by the same row as #TRANS, #BTRANS are removed rows
+                "#RTRANS" | "#BTRANS" => Ok(()),
+                _ => Err(format!("{} i verifikation", label)),
+            };
+        }
+
+        let sie = &mut self.sie;
+        match label.as_str() {
+            "#FLAGGA" => sie.flag = parse_number(fields.required(0, "flagga")?)?,
+            "#SIETYP" => sie.sie_type = Some(parse_number(fields.required(0, "SIE-typ")?)?),
+            "#PROGRAM" => {
+                sie.program = fields.optional(0).map(str::to_string);
+                sie.program_version = fields.optional(1).map(str::to_string);
+            }
+            "#GEN" => sie.generated = Some(parse_date(fields.required(0, "datum")?)?),
+            "#FNAMN" => sie.company_name = fields.optional(0).map(str::to_string),
+            "#KONTO" => sie.accounts.push(Account {
+                number: fields.required(0, "kontonummer")?.to_string(),
+                name: fields.get(1).unwrap_or_default().to_string(),
+            }),
+            "#DIM" => sie.dimensions.push(Dimension {
+                number: parse_number(fields.required(0, "dimension")?)?,
+                name: fields.get(1).unwrap_or_default().to_string(),
+            }),
+            "#OBJEKT" => sie.objects.push(Object {
+                dimension: parse_number(fields.required(0, "dimension")?)?,
+                number: fields.required(1, "objektnummer")?.to_string(),
+                name: fields.get(2).unwrap_or_default().to_string(),
+            }),
+            "#VER" => {
+                self.voucher = Some(Voucher {
+                    series: fields.get(0).unwrap_or_default().to_string(),
+                    number: fields.get(1).unwrap_or_default().to_string(),
+                    date: parse_date(fields.required(2, "verifikationsdatum")?)?,
+                    text: fields.get(3).unwrap_or_default().to_string(),
+                    transactions: Vec::new(),
+                })
+            }
+            "#TRANS" | "#RTRANS" | "#BTRANS" => return Err(format!("{} utanför verifikation", label)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The first #KSUMMA starts the checksum and the last one, with the value, ends it.
+    fn checksum(&mut self, fields: &Fields) -> Result<(), String> {
+        match (self.checksum.take(), fields.optional(0)) {
+            (None, None) => self.checksum = Some(Hasher::new()),
+            (Some(checksum), Some(value)) => {
+                // Some programs write the sum as a signed number
+                let value: i64 = parse_number(value)?;
+                self.sie.checksum_ok = Some(value as u32 == checksum.finalize());
+            }
+            _ => return Err("#KSUMMA utan början eller värde".to_string()),
+        }
+        Ok(())
+    }
+}
+
+fn parse_trans(fields: &Fields) -> Result<Transaction, String> {
+    let list = fields.list(1, "objektlista")?;
+    if list.len() % 2 != 0 {
+        return Err(format!("ofullständig objektlista {{{}}}", list.join(" ")));
+    }
+    let objects = list
+        .chunks(2)
+        .map(|pair| Ok((parse_number(&pair[0])?, pair[1].clone())))
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Transaction {
+        account: fields.required(0, "kontonummer")?.to_string(),
+        objects,
+        amount: parse_amount(fields.required(2, "belopp")?)?,
+    })
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("ogiltigt tal \"{}\"", value))
+}
+
+fn parse_amount(value: &str) -> Result<Decimal, String> {
+    Decimal::from_str(value).map_err(|_| format!("ogiltigt belopp \"{}\"", value))
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| format!("ogiltigt datum \"{}\"", value))
+}
+
+/// Splits a line into fields. Fields are separated by spaces or tabs, fields with
+/// spaces are quoted and a quote inside a field is written \".
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '{' => {
+                chars.next();
+                let mut list = Vec::new();
+                loop {
+                    match chars.peek() {
+                        None => return Err("} saknas i objektlista".to_string()),
+                        Some(' ' | '\t') => {
+                            chars.next();
+                        }
+                        Some('}') => {
+                            chars.next();
+                            break;
+                        }
+                        Some(_) => list.push(field(&mut chars, '}')?),
+                    }
+                }
+                tokens.push(Token::List(list));
+            }
+            _ => tokens.push(Token::Field(field(&mut chars, '{')?)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn field(chars: &mut Peekable<Chars>, stop: char) -> Result<String, String> {
+    let mut value = String::new();
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        loop {
+            match chars.next() {
+                None => return Err("avslutande citattecken saknas".to_string()),
+                Some('\\') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    value.push('"');
+                }
+                Some('"') => break,
+                Some(c) => value.push(c),
+            }
+        }
+    } else {
+        while let Some(&c) = chars.peek() {
+            if c == ' ' || c == '\t' || c == stop {
+                break;
+            }
+            value.push(c);
+            chars.next();
+        }
+    }
+    Ok(value)
+}
+
+/// A field to write, quoted unless it is a number or date.
+enum Value {
+    Plain(String),
+    Text(String),
+    List(Vec<String>),
+}
+
+/// Writes a SIE4 file, see [`write`].
+struct Writer {
+    out: Vec<u8>,
+    checksum: Hasher,
+}
+
+impl Writer {
+    fn record(&mut self, label: &str, values: &[Value]) {
+        let mut line = label.to_string();
+        self.checksum.update(&encode_pc8(label));
+        for value in values {
+            line.push(' ');
+            match value {
+                Value::Plain(value) => {
+                    line.push_str(value);
+                    self.checksum.update(&encode_pc8(value));
+                }
+                Value::Text(value) => {
+                    line.push_str(&quote(value));
+                    self.checksum.update(&encode_pc8(value));
+                }
+                Value::List(list) => {
+                    let quoted: Vec<String> = list.iter().map(|value| quote(value)).collect();
+                    line.push_str(&format!("{{{}}}", quoted.join(" ")));
+                    list.iter().for_each(|value| self.checksum.update(&encode_pc8(value)));
+                }
+            }
+        }
+        self.line(&line);
+    }
+
+    /// A line outside the checksum.
+    fn line(&mut self, line: &str) {
+        self.out.extend(encode_pc8(line));
+        self.out.extend(b"\r\n");
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+fn date(date: &NaiveDate) -> Value {
+    Value::Plain(date.format("%Y%m%d").to_string())
+}
+
+/// Writes the file in PC8 with a #KSUMMA checksum over all records. The checksum is
+/// a CRC-32 of the labels and field contents, without separators, quotes around
+/// fields, braces or line breaks, as in the SIE 4B specification.
+pub fn write(sie: &Sie) -> Vec<u8> {
+    let mut w = Writer { out: Vec::new(), checksum: Hasher::new() };
+    w.line(&format!("#FLAGGA {}", sie.flag));
+    w.line("#KSUMMA");
+    if let Some(program) = &sie.program {
+        let version = sie.program_version.clone().unwrap_or_default();
+        w.record("#PROGRAM", &[Value::Text(program.clone()), Value::Text(version)]);
+    }
+    w.record("#FORMAT", &[Value::Plain("PC8".to_string())]);
+    if let Some(generated) = &sie.generated {
+        w.record("#GEN", &[date(generated)]);
+    }
+    w.record("#SIETYP", &[Value::Plain(sie.sie_type.unwrap_or(4).to_string())]);
+    // #FNAMN is mandatory, also when the name is not known
+    w.record("#FNAMN", &[Value::Text(sie.company_name.clone().unwrap_or_default())]);
+    for dimension in sie.dimensions.iter() {
+        w.record("#DIM", &[Value::Plain(dimension.number.to_string()), Value::Text(dimension.name.clone())]);
+    }
+    for object in sie.objects.iter() {
+        w.record("#OBJEKT", &[
+            Value::Plain(object.dimension.to_string()),
+            Value::Text(object.number.clone()),
+            Value::Text(object.name.clone()),
+        ]);
+    }
+    for account in sie.accounts.iter() {
+        w.record("#KONTO", &[Value::Plain(account.number.clone()), Value::Text(account.name.clone())]);
+    }
+    for voucher in sie.vouchers.iter() {
+        w.record("#VER", &[
+            Value::Text(voucher.series.clone()),
+            Value::Text(voucher.number.clone()),
+            date(&voucher.date),
+            Value::Text(voucher.text.clone()),
+        ]);
+        w.line("{");
+        for transaction in voucher.transactions.iter() {
+            let objects = transaction.objects.iter().flat_map(|(d, o)| [d.to_string(), o.clone()]).collect();
+            w.record("#TRANS", &[
+                Value::Plain(transaction.account.clone()),
+                Value::List(objects),
+                Value::Plain(format!("{:.2}", transaction.amount)),
+            ]);
+        }
+        w.line("}");
+    }
+    let checksum = w.checksum.clone().finalize();
+    w.line(&format!("#KSUMMA {}", checksum));
+    w.out
+}
@@ -0,0 +1,175 @@
+use crate::bokio::JournalEntry;
+use crate::error::Error;
+use crate::eskassa::{SIEReportListItem, ZReportKey, ZReportListItem};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Imports recorded locally, so that a booked Z-report is recognised even when the
+/// journal entry has another date or title in Bokio than esbokio would give it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    pub imports: Vec<ImportRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImportRecord {
+    pub company_id: String,
+    pub machine_id: String,
+    /// The Z-report id in dinkassa.se.
+    pub zreport_id: String,
+    pub register: u32,
+    pub number: u32,
+    pub date: String,
+    pub journal_entry_id: String,
+    pub journal_entry_number: String,
+    #[serde(default)]
+    pub uploads: Vec<String>,
+    /// See [`content_hash`].
+    pub hash: String,
+    pub imported: String,
+    /// Steps done so far. Empty for imports recorded by `reconcile`, which were
+    /// made some other way.
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    /// The PDF as saved locally, kept until it has been uploaded.
+    #[serde(default)]
+    pub pdf: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ImportStatus>,
+}
+
+/// The steps of an import, in order.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    PdfFetched,
+    Sie4Fetched,
+    EntryCreated,
+    AttachmentUploaded,
+}
+
+/// Why an import is unfinished, when it did not just stop.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// The journal entry was created but the upload of the PDF failed.
+    AttachmentMissing,
+}
+
+impl ImportRecord {
+    pub fn new(
+        company_id: &str,
+        machine_id: &str,
+        report: &ZReportListItem,
+        key: &ZReportKey,
+        sie: &SIEReportListItem,
+    ) -> Self {
+        ImportRecord {
+            company_id: company_id.to_string(),
+            machine_id: machine_id.to_string(),
+            zreport_id: report.id.clone(),
+            register: key.register,
+            number: key.number,
+            date: key.date.clone(),
+            journal_entry_id: String::new(),
+            journal_entry_number: String::new(),
+            uploads: Vec::new(),
+            hash: content_hash(sie),
+            imported: Utc::now().to_rfc3339(),
+            steps: Vec::new(),
+            pdf: None,
+            status: None,
+        }
+    }
+
+    pub fn set_journal_entry(&mut self, journal_entry: &JournalEntry) {
+        self.journal_entry_id = journal_entry.id.clone();
+        self.journal_entry_number = journal_entry.journal_entry_number.clone();
+    }
+
+    /// Records an uploaded PDF. Imports recorded by `reconcile` get no steps.
+    pub fn set_uploaded(&mut self, upload_id: String) {
+        self.uploads.push(upload_id);
+        self.pdf = None;
+        self.status = None;
+        if !self.steps.is_empty() && !self.done(Step::AttachmentUploaded) {
+            self.steps.push(Step::AttachmentUploaded);
+        }
+    }
+
+    pub fn done(&self, step: Step) -> bool {
+        self.steps.contains(&step)
+    }
+
+    /// Started by esbokio but not finished, e.g. the upload of the PDF failed.
+    pub fn is_unfinished(&self) -> bool {
+        !self.steps.is_empty() && !self.done(Step::AttachmentUploaded)
+    }
+}
+
+/// SHA-256 of the SIE report as JSON, to notice reports changed after they were booked.
+pub fn content_hash(sie: &SIEReportListItem) -> String {
+    let json = serde_json::to_vec(sie).unwrap();
+    Sha256::digest(json).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl State {
+    /// Loads the state, or an empty state if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<State, Error> {
+        let mut state = match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice::<State>(&json)
+                .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(Error::io(path.display(), e)),
+        };
+        state.path = Some(path.to_path_buf());
+        Ok(state)
+    }
+
+    /// Writes the state to a temporary file first, so that an interrupted run does
+    /// not leave a truncated file.
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+        path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&tmp, json))
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| Error::io(path.display(), e))
+    }
+
+    pub fn find(&self, company_id: &str, zreport_id: &str) -> Option<&ImportRecord> {
+        self.imports
+            .iter()
+            .find(|r| r.company_id == company_id && r.zreport_id == zreport_id)
+    }
+
+    /// The record for a Z-report from a file, where the id in dinkassa.se is unknown.
+    pub fn find_key(&self, company_id: &str, key: &ZReportKey) -> Option<&ImportRecord> {
+        self.imports.iter().find(|r| {
+            r.company_id == company_id && r.register == key.register && r.number == key.number && r.date == key.date
+        })
+    }
+
+    /// Adds the record, replacing an earlier record for the same Z-report.
+    pub fn record(&mut self, record: ImportRecord) {
+        self.remove(&record.company_id, &record.zreport_id);
+        self.imports.push(record);
+    }
+
+    pub fn remove(&mut self, company_id: &str, zreport_id: &str) -> Option<ImportRecord> {
+        let pos = self
+            .imports
+            .iter()
+            .position(|r| r.company_id == company_id && r.zreport_id == zreport_id)?;
+        Some(self.imports.remove(pos))
+    }
+}
@@ -1,9 +1,12 @@
 use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
 use std::str::FromStr;
 use chrono::{Datelike, NaiveDate};
 use chrono::naive::Days;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
+use crate::error::Error;
 
 #[derive(Debug)]
 pub struct PageReq {
@@ -17,7 +20,11 @@ pub fn format_local_date(date: &NaiveDate) -> String {
 
 pub const DEFAULT_USER_AGENT: &str =
     "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:140.0) Gecko/20100101 Firefox/140.0";
-pub const APPLICATION_JSON: &'static str = "application/json";
+pub const APPLICATION_JSON: &str = "application/json";
+pub const ESBOKIO_USER_AGENT: &str = concat!("esbokio/", env!("CARGO_PKG_VERSION"));
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
 
 fn read_prompt(prompt: &str) -> std::io::Result<String> {
     print!("{}", prompt);
@@ -37,7 +44,7 @@ pub fn read_prompt_trim(prompt: &str) -> String {
 fn read_password(prompt: &str) -> std::io::Result<String> {
     // IntelliJ console is broken giving "device not ready" for /dev/tty.
     // Strangely the builtin terminal works fine.
-    if std::io::stdin().is_terminal() && !std::env::var("BROKEN_TERMINAL").is_ok() {
+    if std::io::stdin().is_terminal() && std::env::var("BROKEN_TERMINAL").is_err() {
         rpassword::prompt_password(prompt)
     } else {
         read_prompt(prompt)
@@ -48,22 +55,38 @@ pub fn read_password_trim(prompt: &str) -> String {
     read_password(prompt).unwrap().trim().to_string()
 }
 
-pub fn to_date(s: String) -> NaiveDate {
-    if s == "today" || s == "0" {
-        chrono::Local::now().date_naive()
+pub fn to_date(s: String) -> Result<NaiveDate, Error> {
+    let today = chrono::Local::now().date_naive();
+    let date = if s == "today" || s == "0" {
+        Some(today)
     } else if s == "yesterday" {
-        chrono::Local::now().date_naive().pred_opt().unwrap()
+        today.pred_opt()
     } else if s == "week" {
-        let now = chrono::Local::now().date_naive();
-        let days = Days::new(now.weekday().num_days_from_monday().to_u64().unwrap());
-        now.checked_sub_days(days).unwrap()
+        let days = Days::new(today.weekday().num_days_from_monday().to_u64().unwrap());
+        today.checked_sub_days(days)
     } else if s == "month" || s == "first" {
-        chrono::Local::now().date_naive().with_day(1).unwrap()
-    } else if s.starts_with("-") && s.chars().skip(1).all(|c| c.is_digit(10)) {
-        let days = u64::from_str(&s[1..]).unwrap();
-        chrono::Local::now().date_naive().checked_sub_days(Days::new(days)).unwrap()
+        today.with_day(1)
+    } else if s.starts_with("-") && s.len() > 1 && s.chars().skip(1).all(|c| c.is_ascii_digit()) {
+        u64::from_str(&s[1..]).ok().and_then(|days| today.checked_sub_days(Days::new(days)))
     } else {
-        NaiveDate::from_str(&s).unwrap()
+        NaiveDate::from_str(&s).ok()
+    };
+    date.ok_or_else(|| Error::Validation(format!("Ogiltigt datum: {}", s)))
+}
+
+/// Parses a four-digit BAS account number (1000-8999).
+pub fn tolka_konto(nummer: &str) -> Option<i32> {
+    if nummer.len() != 4 || !nummer.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    i32::from_str(nummer).ok().filter(|n| (1000..=8999).contains(n))
+}
+
+/// Splits "42" or "k1:42" into register and number.
+pub fn tolka_nummer(part: &str) -> Option<(Option<u32>, u32)> {
+    match part.trim_start_matches('k').split_once(':') {
+        Some((register, number)) => Some((Some(register.parse::<u32>().ok()?), number.parse::<u32>().ok()?)),
+        None => Some((None, part.parse::<u32>().ok()?)),
     }
 }
 
@@ -71,10 +94,31 @@ pub fn money(n: Decimal) -> String {
     format!("{:.2}", n)
 }
 
-pub fn get_env_or_default(key: &str, default: &str) -> String {
-    std::env::var(key).unwrap_or(default.into())
+/// Per-user directory for esbokio under `xdg_var`, or under `fallback` in the home
+/// directory when it is not set. In Windows under `windows_var`.
+fn user_dir(windows_var: &str, xdg_var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os(windows_var).map(PathBuf::from)
+    } else {
+        std::env::var_os(xdg_var)
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+    };
+    base.map(|dir| dir.join("esbokio"))
+}
+
+/// Per-user cache directory for esbokio, e.g. `~/.cache/esbokio`.
+pub fn cache_dir() -> Option<PathBuf> {
+    user_dir("LOCALAPPDATA", "XDG_CACHE_HOME", ".cache")
+}
+
+/// Per-user config directory for esbokio, e.g. `~/.config/esbokio`.
+pub fn config_dir() -> Option<PathBuf> {
+    user_dir("APPDATA", "XDG_CONFIG_HOME", ".config")
 }
 
-pub fn get_env(key: &str) -> String {
-    get_env_or_default(key, "")
+/// Per-user data directory for esbokio, e.g. `~/.local/share/esbokio`.
+pub fn data_dir() -> Option<PathBuf> {
+    user_dir("APPDATA", "XDG_DATA_HOME", ".local/share")
 }
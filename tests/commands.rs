@@ -0,0 +1,201 @@
+//! End-to-end tests for the subcommands other than `import`.
+
+mod mock;
+
+use mock::{PERIOD, TestEnv, import_all, run, stderr, stdout};
+
+#[test]
+fn prints_help_and_version() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("--help");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("reverse"));
+    assert!(stdout(&output).contains("BOKIO_API_TOKEN"));
+
+    let mut cmd = env.command();
+    cmd.arg("--version");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn rejects_unknown_option() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("--bogus");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--bogus"));
+    assert!(env.bokio.requests().is_empty());
+}
+
+#[test]
+fn ignores_empty_environment_variables() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes"])
+        .env("BOKIO_ACCOUNT_MAP", "")
+        .env("BOKIO_ROUNDING_ACCOUNT", "");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(env.bokio.kassa_entries().len(), 1);
+}
+
+#[test]
+fn lists_reports() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("list").args(PERIOD);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("2 Z-Rapporter för Testbutiken AB Kassa 1"));
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn accepts_global_options_before_subcommand() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "list"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 Z-Rapporter för Testbutiken AB Kassa 1 (2026-01-04 - 2026-01-04)"));
+}
+
+#[test]
+fn rejects_import_options_before_other_subcommand() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--yes", "list"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("kan inte anges före ett annat kommando"));
+    assert!(env.bokio.requests().is_empty());
+}
+
+#[test]
+fn shows_status() {
+    let env = TestEnv::new();
+    env.bokio
+        .add_journal_entry("Kassa 1, Z-Rapport #1", "2026-01-04", &[(1580, 1250.0, 0.0)]);
+    let mut cmd = env.command();
+    cmd.arg("status").args(PERIOD).args(["--summary", "summary.json"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 bokförda, 1 ej bokförda"));
+
+    let summary: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("summary.json")).unwrap()).unwrap();
+    assert_eq!(summary["already_imported"], 1);
+    assert_eq!(summary["reports"][1]["status"], "skipped");
+}
+
+#[test]
+fn verifies_journal_entries() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    let mut cmd = env.command();
+    cmd.arg("verify").args(PERIOD);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output).matches(" OK").count(), 2);
+}
+
+#[test]
+fn reports_journal_entry_differences() {
+    let env = TestEnv::new();
+    env.bokio
+        .add_journal_entry("Kassa 1, Z-Rapport #1", "2026-01-04", &[(1580, 1250.0, 0.0), (3001, 0.0, 1250.0)]);
+    let mut cmd = env.command();
+    cmd.arg("verify").args(["--date", "2026-01-04"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    let out = stdout(&output);
+    assert!(out.contains("avviker"));
+    assert!(out.contains("konto 3001: -1250.00 i Bokio, -1150.00 enligt Z-Rapporten"));
+}
+
+#[test]
+fn reverses_journal_entry() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    let mut cmd = env.command();
+    cmd.arg("reverse").args(PERIOD).args(["1", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Verifikat V2 vändes med verifikat V4"));
+
+    let entries = env.bokio.journal_entries();
+    assert_eq!(entries[1]["reversedByJournalEntryId"], "je-4");
+    assert_eq!(entries[3]["reversingJournalEntryId"], "je-2");
+    assert_eq!(entries[3]["items"][0]["credit"], 1250.0);
+
+    // The reversed Z-report can be imported again
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 Z-Rapporter importerades"));
+    assert_eq!(env.bokio.kassa_entries().len(), 3);
+}
+
+#[test]
+fn does_not_reverse_unbooked_report() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("reverse").args(PERIOD).args(["2", "--yes"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(stderr(&output).contains("Z-Rapport 2 hittades inte"));
+    assert!(env.bokio.requests().iter().all(|r| r.starts_with("GET")));
+}
+
+#[test]
+fn exports_reports() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("export").args(PERIOD).args(["--output", "export"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+
+    for basename in ["Z1_Kassa 1_Testbutiken AB", "Z2_Kassa 1_Testbutiken AB"] {
+        for ext in ["pdf", "si", "json", "bokio.json"] {
+            assert!(env.path(&format!("export/{}.{}", basename, ext)).exists());
+        }
+    }
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn uploads_missing_attachments() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    env.bokio.state.lock().unwrap().uploads.remove(0);
+
+    let mut cmd = env.command();
+    cmd.arg("verify-attachments").args(PERIOD);
+    let output = run(cmd, "n\n");
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("K1:1 (2026-01-04): V2 saknar underlag"));
+    assert!(out.contains("K1:2 (2026-01-05): V3 OK"));
+    assert_eq!(env.bokio.uploads().len(), 1);
+
+    let mut cmd = env.command();
+    cmd.arg("verify-attachments").args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    let uploads = env.bokio.uploads();
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[1].journal_entry_id, "je-2");
+    assert_eq!(uploads[1].filename, "Z1_Kassa 1_Testbutiken AB.pdf");
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+
+    let state: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("data/esbokio/state.json")).unwrap()).unwrap();
+    let post = state["imports"].as_array().unwrap().iter().find(|p| p["journal_entry_id"] == "je-2").unwrap();
+    assert_eq!(post["uploads"].as_array().unwrap().len(), 2);
+}
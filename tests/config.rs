@@ -0,0 +1,130 @@
+//! End-to-end tests for profiles in the config file.
+
+mod mock;
+
+use mock::{PERIOD, TestEnv, bokio, dinkassa, run, stdout};
+
+const CONFIG: &str = r#"
+default_profile = "butik"
+
+[profile.butik]
+dinkassa_username = "kassa@example.com"
+dinkassa_password = { env = "BUTIK_PASSWORD" }
+bokio_company_id = "company-1"
+bokio_api_token = { env = "BUTIK_TOKEN" }
+machine = "Kassa 2"
+output_dir = "butik"
+
+[profile.annan]
+bokio_company_id = "company-2"
+"#;
+
+/// Writes the config file to the default location and removes the settings it provides from the environment.
+fn with_config(env: &TestEnv, config: &str) -> std::process::Command {
+    std::fs::create_dir_all(env.path("config/esbokio")).unwrap();
+    std::fs::write(env.path("config/esbokio/config.toml"), config).unwrap();
+    let mut cmd = env.command();
+    cmd.env_remove("DINKASSA_USERNAME")
+        .env_remove("DINKASSA_PASSWORD")
+        .env_remove("BOKIO_API_TOKEN")
+        .env_remove("BOKIO_COMPANY_ID")
+        .env("BUTIK_PASSWORD", dinkassa::PASSWORD)
+        .env("BUTIK_TOKEN", bokio::TOKEN);
+    cmd
+}
+
+#[test]
+fn uses_default_profile() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, CONFIG);
+    cmd.args(PERIOD).args(["--yes", "--save-files"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["title"], "Kassa 2, Z-Rapport #1");
+    assert!(env.path("config/esbokio/butik/Z1_Kassa 2_Testbutiken AB.pdf").exists());
+    assert_eq!(env.bokio.uploads()[0].filename, "Z1_Kassa 2_Testbutiken AB.pdf");
+}
+
+#[test]
+fn command_line_and_environment_override_profile() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, CONFIG);
+    cmd.args(PERIOD)
+        .args(["--yes", "--machine", "Kassa 1"])
+        .env("ESBOKIO_OUTPUT_DIR", "filer")
+        .arg("--save-files");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("2 Z-Rapporter importerades"));
+    assert!(env.path("filer/Z1_Kassa 1_Testbutiken AB.pdf").exists());
+}
+
+#[test]
+fn selects_profile() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, CONFIG);
+    cmd.args(PERIOD).args(["--yes", "--profile", "annan"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("DINKASSA_USERNAME saknas"));
+}
+
+#[test]
+fn accepts_profile_before_subcommand() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, CONFIG);
+    cmd.args(["--profile", "butik", "status"]).args(PERIOD);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 Z-Rapporter för Testbutiken AB Kassa 2"));
+}
+
+#[test]
+fn rejects_unknown_profile() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, CONFIG);
+    cmd.args(["list", "--profile", "okand"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Profilen okand finns inte, tillgängliga profiler: annan, butik"));
+}
+
+#[test]
+fn fails_when_secret_is_missing() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, CONFIG);
+    cmd.args(PERIOD).arg("--yes").env_remove("BUTIK_TOKEN");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("miljövariabeln BUTIK_TOKEN saknas"));
+    assert!(env.bokio.requests().is_empty());
+}
+
+#[test]
+fn rejects_invalid_config() {
+    let env = TestEnv::new();
+    let mut cmd = with_config(&env, "[profile.butik]\nbokio_company = \"company-1\"\n");
+    cmd.arg("list");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bokio_company"));
+}
+
+#[test]
+fn profile_with_ledger_books_without_bokio() {
+    let env = TestEnv::new();
+    let config = "[profile.fil]\ndinkassa_username = \"kassa@example.com\"\n\
+                  dinkassa_password = { env = \"BUTIK_PASSWORD\" }\nledger = \"bokforing\"\n";
+    let mut cmd = with_config(&env, config);
+    cmd.args(PERIOD).args(["--yes", "--profile", "fil"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout(&output).contains("2 Z-Rapporter importerades"));
+    assert!(env.path("config/esbokio/bokforing/journal.json").exists());
+    assert!(env.path("config/esbokio/bokforing/journal.se").exists());
+    assert!(env.bokio.requests().is_empty());
+}
@@ -0,0 +1,144 @@
+//! End-to-end tests for reading passwords and tokens from files, commands and the keyring.
+
+#![cfg(unix)]
+
+mod mock;
+
+use mock::{PERIOD, TestEnv, bokio, dinkassa, run, stderr, stdout};
+use std::os::unix::fs::PermissionsExt;
+
+/// Stand-in for secret-tool keeping each entry in a file.
+const SECRET_TOOL: &str = r#"#!/bin/sh
+dir="$(dirname "$0")/../keyring"
+case "$1" in
+    lookup) cat "$dir/$(echo "$5" | tr / _)" 2>/dev/null ;;
+    store) mkdir -p "$dir" && cat > "$dir/$(echo "$7" | tr / _)" ;;
+    *) exit 2 ;;
+esac
+"#;
+
+/// The command without the password and token, with the fake secret-tool first in PATH.
+fn without_secrets(env: &TestEnv) -> std::process::Command {
+    let bin = env.path("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    std::fs::write(bin.join("secret-tool"), SECRET_TOOL).unwrap();
+    std::fs::set_permissions(bin.join("secret-tool"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut cmd = env.command();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+    cmd.env("PATH", path)
+        .env_remove("DINKASSA_PASSWORD")
+        .env_remove("BOKIO_API_TOKEN");
+    cmd
+}
+
+fn keyring_entry(env: &TestEnv, name: &str) -> Option<String> {
+    std::fs::read_to_string(env.path("keyring").join(name)).ok()
+}
+
+#[test]
+fn reads_secrets_from_file_and_command() {
+    let env = TestEnv::new();
+    std::fs::write(env.path("password"), format!("{}\n", dinkassa::PASSWORD)).unwrap();
+    let mut cmd = without_secrets(&env);
+    cmd.args(PERIOD)
+        .arg("--yes")
+        .env("DINKASSA_PASSWORD_FILE", env.path("password"))
+        .env("BOKIO_API_TOKEN_COMMAND", format!("printf '{}\\nrad 2'", bokio::TOKEN));
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+}
+
+#[test]
+fn reads_secrets_from_profile() {
+    let env = TestEnv::new();
+    std::fs::create_dir_all(env.path("config/esbokio")).unwrap();
+    std::fs::write(env.path("config/esbokio/password"), dinkassa::PASSWORD).unwrap();
+    std::fs::write(
+        env.path("config/esbokio/config.toml"),
+        format!(
+            "[profile.butik]\n\
+             dinkassa_password = {{ file = \"password\" }}\n\
+             bokio_api_token = {{ command = \"echo {}\" }}\n",
+            bokio::TOKEN
+        ),
+    )
+    .unwrap();
+    let mut cmd = without_secrets(&env);
+    cmd.args(PERIOD).args(["--yes", "--profile", "butik"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+}
+
+#[test]
+fn fails_when_password_command_fails() {
+    let env = TestEnv::new();
+    let mut cmd = without_secrets(&env);
+    cmd.args(PERIOD)
+        .arg("--yes")
+        .env("DINKASSA_PASSWORD_COMMAND", "exit 1")
+        .env("BOKIO_API_TOKEN", bokio::TOKEN);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("kommandot \"exit 1\" misslyckades"));
+    assert_eq!(env.dinkassa.authentications(), 0);
+}
+
+#[test]
+fn reads_only_secrets_needed_for_login() {
+    let env = TestEnv::new();
+    let mut cmd = without_secrets(&env);
+    cmd.args(PERIOD)
+        .args(["--yes", "--ledger", "bokforing"])
+        .env("DINKASSA_PASSWORD", dinkassa::PASSWORD)
+        .env("DINKASSA_MACHINE_KEY_COMMAND", "exit 1")
+        .env("BOKIO_API_TOKEN_COMMAND", "exit 1");
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("2 Z-Rapporter importerades"));
+}
+
+#[test]
+fn login_saves_secrets_in_keyring() {
+    let env = TestEnv::new();
+    let mut cmd = without_secrets(&env);
+    cmd.arg("login");
+    let output = run(cmd, &format!("{}\n{}\n", dinkassa::PASSWORD, bokio::TOKEN));
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("BOKIO_API_TOKEN sparades i nyckelringen"));
+    assert_eq!(keyring_entry(&env, "DINKASSA_PASSWORD").as_deref(), Some(dinkassa::PASSWORD));
+    assert_eq!(keyring_entry(&env, "BOKIO_API_TOKEN").as_deref(), Some(bokio::TOKEN));
+
+    // Later runs read the secrets from the keyring without asking
+    let mut cmd = without_secrets(&env);
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+}
+
+#[test]
+fn login_uses_profile_name_in_keyring() {
+    let env = TestEnv::new();
+    std::fs::create_dir_all(env.path("config/esbokio")).unwrap();
+    std::fs::write(env.path("config/esbokio/config.toml"), "[profile.butik]\n").unwrap();
+    let mut cmd = without_secrets(&env);
+    cmd.args(["login", "--profile", "butik"]);
+    let output = run(cmd, &format!("{}\n{}\n", dinkassa::PASSWORD, bokio::TOKEN));
+    assert!(output.status.success());
+    assert_eq!(keyring_entry(&env, "butik_BOKIO_API_TOKEN").as_deref(), Some(bokio::TOKEN));
+    assert_eq!(keyring_entry(&env, "BOKIO_API_TOKEN"), None);
+}
+
+#[test]
+fn login_does_not_save_wrong_token() {
+    let env = TestEnv::new();
+    let mut cmd = without_secrets(&env);
+    cmd.arg("login");
+    let output = run(cmd, &format!("{}\nfel-token\n", dinkassa::PASSWORD));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Inloggning i Bokio misslyckades"));
+    assert_eq!(keyring_entry(&env, "DINKASSA_PASSWORD"), None);
+}
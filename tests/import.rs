@@ -0,0 +1,710 @@
+//! End-to-end tests running esbokio against the mock servers in `tests/mock`.
+
+mod mock;
+
+use esbokio::sie;
+use mock::{PERIOD, TestEnv, dinkassa, run, stdout};
+use rust_decimal::Decimal;
+use serde_json::json;
+
+#[test]
+fn imports_all_reports() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("2 Z-Rapporter importerades"));
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["title"], "Kassa 1, Z-Rapport #1");
+    assert_eq!(entries[0]["date"], "2026-01-04");
+    assert_eq!(
+        entries[0]["items"],
+        json!([
+            { "id": 1, "account": 1580, "debit": 1250.0, "credit": 0.0 },
+            { "id": 2, "account": 1910, "debit": 187.5, "credit": 0.0 },
+            { "id": 3, "account": 3001, "debit": 0.0, "credit": 1150.0 },
+            { "id": 4, "account": 2611, "debit": 0.0, "credit": 287.5 },
+        ])
+    );
+    assert_eq!(entries[1]["title"], "Kassa 1, Z-Rapport #2");
+
+    let uploads = env.bokio.uploads();
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[0].journal_entry_id, entries[0]["id"]);
+    assert_eq!(uploads[0].filename, "Z1_Kassa 1_Testbutiken AB.pdf");
+    assert_eq!(uploads[0].content_type, "application/pdf");
+    assert_eq!(uploads[0].data, dinkassa::pdf("z-1-1"));
+
+    // Files are only kept with --save-files
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+}
+
+#[test]
+fn skips_already_imported_reports() {
+    let env = TestEnv::new();
+    env.bokio
+        .add_journal_entry("Kassa 1, Z-Rapport #1", "2026-01-04", &[(1580, 1250.0, 0.0)]);
+    let mut cmd = env.command();
+    cmd.args(PERIOD);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 Z-Rapporter importerades"));
+    assert!(stdout(&output).contains("1 Z-Rapporter redan importerade"));
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1]["title"], "Kassa 1, Z-Rapport #2");
+}
+
+#[test]
+fn imports_selected_report() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD);
+    let output = run(cmd, "2\n");
+    assert!(output.status.success());
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["title"], "Kassa 1, Z-Rapport #2");
+}
+
+#[test]
+fn saves_files() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--save-files"]);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+
+    let basename = "Z1_Kassa 1_Testbutiken AB";
+    assert_eq!(
+        std::fs::read(env.path(&format!("{}.pdf", basename))).unwrap(),
+        dinkassa::pdf("z-1-1")
+    );
+    assert!(env.path(&format!("{}.si", basename)).exists());
+    assert!(env.path(&format!("{}.json", basename)).exists());
+    assert!(env.path(&format!("{}.bokio.json", basename)).exists());
+}
+
+/// The SIE4 file for K1:1 with objects, quoted fields and a corrected row.
+const SIE4_K1_1: &str = r#"#FLAGGA 0
+#FORMAT PC8
+#SIETYP 4
+#PROGRAM "ES Kassa" 1.0
+#GEN 20260104
+#FNAMN "Testbutiken \"Syd\" AB"
+#DIM 1 "Kostnadsställe"
+#OBJEKT 1 "10" "Butik"
+#KONTO 3001 "Försäljning 25%"
+#VER "" "" 20260104 "K1:1"
+{
+   #TRANS 1580 {} 1250.00
+   #TRANS 1910 {} 187.50 "" "Växelkassa"
+   #RTRANS 3001 {1 "10"} -1150.00
+   #TRANS 3001 {1 "10"} -1150.00
+   #TRANS 2611 {} -287.50
+}
+"#;
+
+#[test]
+fn reads_sie4_file() {
+    let env = TestEnv::new();
+    env.dinkassa.set_sie4("z-1-1", SIE4_K1_1);
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Varning"));
+}
+
+#[test]
+fn parses_sie4_file() {
+    let sie4 = sie::parse(&dinkassa::encode_pc8(SIE4_K1_1)).unwrap();
+    assert_eq!(sie4.flag, 0);
+    assert_eq!(sie4.sie_type, Some(4));
+    assert_eq!(sie4.program.as_deref(), Some("ES Kassa"));
+    assert_eq!(sie4.program_version.as_deref(), Some("1.0"));
+    assert_eq!(sie4.company_name.as_deref(), Some("Testbutiken \"Syd\" AB"));
+    assert_eq!(sie4.dimensions[0].number, 1);
+    assert_eq!(sie4.dimensions[0].name, "Kostnadsställe");
+    assert_eq!((sie4.objects[0].dimension, sie4.objects[0].number.as_str()), (1, "10"));
+    assert_eq!(sie4.objects[0].name, "Butik");
+    assert_eq!(sie4.accounts[0].name, "Försäljning 25%");
+    assert_eq!(sie4.checksum_ok, None);
+
+    // The #RTRANS row is skipped, the #TRANS row after it replaces it
+    let transactions = &sie4.vouchers[0].transactions;
+    assert_eq!(transactions.len(), 4);
+    assert_eq!(transactions[2].account, "3001");
+    assert_eq!(transactions[2].objects, vec![(1, "10".to_string())]);
+    assert_eq!(transactions[2].amount, Decimal::new(-115000, 2));
+    assert_eq!(sie4.account_totals()["3001"], Decimal::new(-115000, 2));
+}
+
+#[test]
+fn does_not_import_unreadable_sie4_file() {
+    let env = TestEnv::new();
+    env.dinkassa.set_sie4("z-1-1", &SIE4_K1_1.replace("20260104 \"K1:1\"", "20260104 \"K1:1"));
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Kunde inte tolka SIE4 rad 10: avslutande citattecken saknas"));
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn does_not_import_report_differing_from_sie4_file() {
+    let env = TestEnv::new();
+    env.dinkassa.set_sie4("z-1-1", &SIE4_K1_1.replace("#TRANS 1910 {} 187.50", "#TRANS 1910 {} 177.50"));
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    assert!(stderr.contains("Varning: Z1_Kassa 1_Testbutiken AB.si stämmer inte med Z-Rapporten"));
+    assert!(stderr.contains("konto 1910: 177.50 i SIE4-filen, 187.50 enligt Z-Rapporten"));
+    assert!(env.bokio.kassa_entries().is_empty());
+    // Nothing is left of the declined import
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.si").exists());
+    let state: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("data/esbokio/state.json")).unwrap()).unwrap();
+    assert_eq!(state["imports"], serde_json::json!([]));
+
+    // Booked anyway when confirmed in the menu
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04"]);
+    let output = run(cmd, "j\nj\n");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Bokför ändå (j/N)?"));
+    assert_eq!(env.bokio.kassa_entries().len(), 1);
+}
+
+#[test]
+fn machine_key_login() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04"])
+        .env_remove("DINKASSA_USERNAME")
+        .env_remove("DINKASSA_PASSWORD")
+        .env("DINKASSA_INTEGRATOR_ID", dinkassa::INTEGRATOR_ID)
+        .env("DINKASSA_MACHINE_ID", "m-2")
+        .env("DINKASSA_MACHINE_KEY", "key-2");
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+    assert_eq!(env.dinkassa.authentications(), 0);
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["title"], "Kassa 2, Z-Rapport #1");
+}
+
+#[test]
+fn imports_all_machines() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--machine", "all"]);
+    let output = run(cmd, "j\nj\n");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("3 Z-Rapporter importerades"));
+
+    let titles: Vec<String> = env
+        .bokio
+        .kassa_entries()
+        .iter()
+        .map(|e| e["title"].to_string())
+        .collect();
+    assert_eq!(
+        titles,
+        [
+            "\"Kassa 1, Z-Rapport #1\"",
+            "\"Kassa 1, Z-Rapport #2\"",
+            "\"Kassa 2, Z-Rapport #1\""
+        ]
+    );
+}
+
+#[test]
+fn selects_machine_by_name() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--machine", "kassa 2"]);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["title"], "Kassa 2, Z-Rapport #1");
+}
+
+#[test]
+fn rejects_unknown_machine() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--machine", "Kassa 9", "--yes"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Kassan Kassa 9 hittades inte, tillgängliga kassor: Kassa 1"));
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn skips_report_with_other_date() {
+    let env = TestEnv::new();
+    env.dinkassa.state.lock().unwrap().fixtures["ZReports"]["m-1"][0]["DateTime"] = json!("2026-01-05T09:00:00");
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Varning: Z-Rapport K1:1 (2026-01-04) hittades inte, hoppar över"));
+    assert!(stdout(&output).contains("1 Z-Rapporter importerades"));
+}
+
+#[test]
+fn skips_report_from_other_register() {
+    let env = TestEnv::new();
+    env.dinkassa.state.lock().unwrap().fixtures["ZReports"]["m-1"][1]["RegisterNumber"] = json!(2);
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Varning: Z-Rapport K1:2 (2026-01-05) hittades inte, hoppar över"));
+    assert_eq!(env.bokio.kassa_entries().len(), 1);
+}
+
+#[test]
+fn reuses_cached_session() {
+    let env = TestEnv::new();
+    for _ in 0..2 {
+        let mut cmd = env.command();
+        cmd.args(PERIOD);
+        assert!(run(cmd, "n\n").status.success());
+    }
+    assert_eq!(env.dinkassa.authentications(), 1);
+    assert!(env.path("cache/esbokio/dinkassa-session.json").exists());
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn saved_session_needs_no_password() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--none");
+    assert!(run(cmd, "").status.success());
+
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes").env_remove("DINKASSA_PASSWORD");
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(env.dinkassa.authentications(), 1);
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+}
+
+#[test]
+fn renews_expired_session() {
+    let env = TestEnv::new();
+    env.dinkassa.state.lock().unwrap().session_lifetime = Some(2);
+    let mut cmd = env.command();
+    cmd.args(PERIOD);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+    // Each session is good for two requests, the renewed cookies must be used by the following ones
+    assert_eq!(env.dinkassa.authentications(), 4);
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+    assert_eq!(env.bokio.uploads().len(), 2);
+}
+
+#[test]
+fn fails_on_wrong_password() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.env("DINKASSA_PASSWORD", "fel");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Inloggning på dinkassa.se misslyckades"));
+    assert!(env.bokio.requests().is_empty());
+}
+
+#[test]
+fn fails_on_wrong_bokio_token() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).env("BOKIO_API_TOKEN", "fel");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid token"));
+}
+
+#[test]
+fn reports_http_errors_with_response_body() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).env("BOKIO_COMPANY_ID", "company-2");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HTTP 404"));
+}
+
+#[test]
+fn rejects_invalid_date() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "igår"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Ogiltigt datum: igår"));
+}
+
+#[test]
+fn retries_transient_errors() {
+    let env = TestEnv::new();
+    {
+        let mut state = env.dinkassa.state.lock().unwrap();
+        state.failures.add("get-z-reports", 503, 2);
+        state.failures.add("download-z-report/", 500, 1);
+    }
+    env.bokio.state.lock().unwrap().failures.add("uploads", 429, 1);
+    let mut cmd = env.command();
+    cmd.args(PERIOD);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+    assert!(env.dinkassa.state.lock().unwrap().failures.is_empty());
+    assert!(env.bokio.state.lock().unwrap().failures.is_empty());
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+    assert_eq!(env.bokio.uploads().len(), 2);
+}
+
+#[test]
+fn gives_up_after_retries() {
+    let env = TestEnv::new();
+    env.dinkassa.state.lock().unwrap().failures.add("get-z-reports", 503, 3);
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--retries", "2"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HTTP 503"));
+}
+
+#[test]
+fn does_not_duplicate_journal_entry_when_response_is_lost() {
+    let env = TestEnv::new();
+    env.bokio.state.lock().unwrap().lose_created_entries = 1;
+    let mut cmd = env.command();
+    cmd.args(PERIOD);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 2);
+    let uploads = env.bokio.uploads();
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[0].journal_entry_id, entries[0]["id"]);
+}
+
+#[test]
+fn imports_all_reports_without_asking() {
+    let env = TestEnv::new();
+    env.bokio
+        .add_journal_entry("Kassa 1, Z-Rapport #1", "2026-01-04", &[(1580, 1250.0, 0.0)]);
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--yes", "--summary", "summary.json"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+
+    let summary: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("summary.json")).unwrap()).unwrap();
+    assert_eq!(summary["imported"], 1);
+    assert_eq!(summary["already_imported"], 1);
+    assert_eq!(summary["failed"], 0);
+    assert_eq!(summary["reports"][0]["status"], "already_imported");
+    assert_eq!(summary["reports"][1]["number"], 2);
+    assert_eq!(summary["reports"][1]["status"], "imported");
+    assert_eq!(summary["reports"][1]["journal_entry"], "V3");
+}
+
+#[test]
+fn imports_only_given_reports() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--only", "k1:2"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["title"], "Kassa 1, Z-Rapport #2");
+}
+
+#[test]
+fn fails_on_unknown_report_in_only() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--only", "1,7"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Z-Rapport 7 hittades inte"));
+    assert_eq!(env.bokio.kassa_entries().len(), 1);
+}
+
+#[test]
+fn lists_reports_with_none() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--none", "--summary", "summary.json"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    let summary = std::fs::read_to_string(env.path("summary.json")).unwrap();
+    assert!(summary.contains("\"status\": \"skipped\""));
+    assert!(env.bokio.kassa_entries().is_empty());
+
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--none", "--summary", "-"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sammanfattningen kan inte skrivas till standard output"));
+}
+
+#[test]
+fn exits_with_error_when_import_fails() {
+    let env = TestEnv::new();
+    env.dinkassa.state.lock().unwrap().failures.add("download-z-report/", 404, 1);
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--yes", "--summary", "summary.json"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(3));
+
+    let summary: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("summary.json")).unwrap()).unwrap();
+    assert_eq!(summary["failed"], 1);
+    assert_eq!(summary["reports"][0]["status"], "failed");
+    assert!(summary["reports"][0]["error"].as_str().unwrap().contains("HTTP 404"));
+}
+
+#[test]
+fn does_not_prompt_without_credentials_when_non_interactive() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--yes"]).env_remove("BOKIO_API_TOKEN");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("BOKIO_API_TOKEN saknas"));
+}
+
+#[test]
+fn dry_run_does_not_post() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--dry-run", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("Kassa 1, Z-Rapport #1 2026-01-04"));
+    assert!(out.contains("1250.00"));
+    assert!(out.contains("Underlag: Z1_Kassa 1_Testbutiken AB.pdf"));
+    assert!(out.contains("1 Z-Rapporter förhandsgranskades"));
+
+    assert!(env.bokio.kassa_entries().is_empty());
+    assert!(env.bokio.uploads().is_empty());
+    assert!(env.bokio.requests().iter().all(|r| r.starts_with("GET")));
+
+    let preview: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("Z1_Kassa 1_Testbutiken AB.bokio.json")).unwrap()).unwrap();
+    assert_eq!(preview["title"], "Kassa 1, Z-Rapport #1");
+    assert_eq!(preview["items"].as_array().unwrap().len(), 4);
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+}
+
+/// Changes the amount on an account in the first Z-report of Kassa 1.
+fn set_amount(env: &TestEnv, account: usize, amount: serde_json::Value) {
+    let mut state = env.dinkassa.state.lock().unwrap();
+    state.fixtures["SIEReports"]["m-1"][0]["Accounts"][account]["Amount"] = amount;
+}
+
+#[test]
+fn rejects_unbalanced_journal_entry() {
+    let env = TestEnv::new();
+    set_amount(&env, 1, json!(187.0));
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("inte balanserat: debet 1437.00, kredit 1437.50, differens -0.50"));
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.json").exists());
+
+    // The next report is still imported
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["title"], "Kassa 1, Z-Rapport #2");
+}
+
+#[test]
+fn books_rounding_difference() {
+    let env = TestEnv::new();
+    set_amount(&env, 1, json!(187.0));
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--rounding-account", "3740"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0]["items"][4],
+        json!({ "id": 5, "account": 3740, "debit": 0.5, "credit": 0.0 })
+    );
+}
+
+#[test]
+fn rejects_invalid_account() {
+    let env = TestEnv::new();
+    {
+        let mut state = env.dinkassa.state.lock().unwrap();
+        state.fixtures["SIEReports"]["m-1"][0]["Accounts"][1]["Number"] = json!("191");
+    }
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--rounding-account", "3740"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Ogiltigt kontonummer \"191\" (Kassa)"));
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+const ACCOUNT_MAP: &str = r#"
+[[rule]]
+from = "1580"
+to = "1581"
+
+[[rule]]
+from = ["1910", "1580"]
+to = "1930"
+register = 1
+
+[[rule]]
+from = "3001"
+company = "company-1"
+split = [{ to = "3001", percent = 80 }, { to = "3002", percent = 20 }]
+
+[[rule]]
+from = "2611"
+company = "company-2"
+to = "2612"
+"#;
+
+#[test]
+fn maps_accounts() {
+    let env = TestEnv::new();
+    std::fs::write(env.path("konton.toml"), ACCOUNT_MAP).unwrap();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--account-map", "konton.toml"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Kontomappning K1: 1910 + 1580 → 1930; 3001 → 3001 80 %, 3002 20 %"));
+
+    // The register rule wins over the general rule for 1580, 1580 and 1910 are merged
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(
+        entries[0]["items"],
+        json!([
+            { "id": 1, "account": 1930, "debit": 1437.5, "credit": 0.0 },
+            { "id": 2, "account": 3001, "debit": 0.0, "credit": 920.0 },
+            { "id": 3, "account": 3002, "debit": 0.0, "credit": 230.0 },
+            { "id": 4, "account": 2611, "debit": 0.0, "credit": 287.5 },
+        ])
+    );
+}
+
+#[test]
+fn maps_accounts_per_register() {
+    let env = TestEnv::new();
+    std::fs::write(env.path("konton.toml"), ACCOUNT_MAP).unwrap();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--machine", "Kassa 2", "--dry-run"])
+        .env("BOKIO_ACCOUNT_MAP", "konton.toml");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+
+    let preview: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("Z1_Kassa 2_Testbutiken AB.bokio.json")).unwrap()).unwrap();
+    let accounts: Vec<i64> = preview["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| i["account"].as_i64().unwrap())
+        .collect();
+    assert_eq!(accounts, [1930, 3001, 3002, 2611]);
+    assert!(stdout(&output).contains("FRÅN"));
+}
+
+#[test]
+fn rejects_invalid_account_map() {
+    let env = TestEnv::new();
+    std::fs::write(env.path("konton.toml"), "[[rule]]\nfrom = \"1580\"\nto = \"15810\"\n").unwrap();
+    let mut cmd = env.command();
+    cmd.args(["--yes", "--account-map", "konton.toml"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("regel 1: ogiltigt kontonummer \"15810\""));
+}
+
+fn chart_requests(env: &TestEnv) -> usize {
+    env.bokio.requests().iter().filter(|r| r.ends_with("/chart-of-accounts")).count()
+}
+
+#[test]
+fn rejects_account_missing_in_bokio() {
+    let env = TestEnv::new();
+    std::fs::write(env.path("konton.toml"), "[[rule]]\nfrom = \"1580\"\nto = \"1581\"\n").unwrap();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--account-map", "konton.toml"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Konton saknas i Bokios kontoplan:\n  1581 Kortfordringar, förslag: 1580"));
+    assert!(stderr.contains("([[rule]] from = [\"1580\"], to = \"1580\")"));
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn caches_chart_of_accounts() {
+    let env = TestEnv::new();
+    for date in ["2026-01-04", "2026-01-05"] {
+        let mut cmd = env.command();
+        cmd.args(["--date", date, "--yes"]);
+        assert!(run(cmd, "").status.success());
+    }
+    assert_eq!(chart_requests(&env), 1);
+    assert!(env.path("cache/esbokio/bokio-accounts.json").exists());
+}
+
+#[test]
+fn fetches_chart_of_accounts_again_for_new_account() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--dry-run"]);
+    assert!(run(cmd, "").status.success());
+
+    env.bokio
+        .state
+        .lock()
+        .unwrap()
+        .chart_of_accounts
+        .push(json!({ "account": 1581, "name": "Kortfordringar" }));
+    std::fs::write(env.path("konton.toml"), "[[rule]]\nfrom = \"1580\"\nto = \"1581\"\n").unwrap();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--account-map", "konton.toml"]);
+    assert!(run(cmd, "").status.success());
+    assert_eq!(chart_requests(&env), 2);
+    assert_eq!(env.bokio.kassa_entries()[0]["items"][0]["account"], 1581);
+}
@@ -0,0 +1,117 @@
+//! End-to-end tests for booking Z-reports in a journal in files instead of Bokio.
+
+mod mock;
+
+use mock::{PERIOD, TestEnv, bokio, dinkassa, import_all, run, stderr, stdout};
+use serde_json::{Value, json};
+
+fn read_journal(env: &TestEnv) -> Value {
+    serde_json::from_slice(&std::fs::read(env.path("bokforing/journal.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn imports_into_ledger_without_bokio() {
+    let env = TestEnv::new();
+    import_all(&env, &["--ledger", "bokforing"]);
+    assert!(env.bokio.requests().is_empty());
+
+    let journal = read_journal(&env);
+    let entries = journal["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["title"], "Kassa 1, Z-Rapport #1");
+    assert_eq!(entries[0]["journalEntryNumber"], "V1");
+    assert_eq!(entries[0]["date"], "2026-01-04");
+    assert_eq!(entries[0]["items"][0], json!({ "id": 1, "account": 1580, "debit": 1250.0, "credit": 0.0 }));
+    assert_eq!(journal["uploads"][0]["journalEntryId"], entries[0]["id"]);
+    assert_eq!(
+        std::fs::read(env.path("bokforing/underlag/V1-Z1_Kassa 1_Testbutiken AB.pdf")).unwrap(),
+        dinkassa::pdf("z-1-1")
+    );
+
+    let sie4 = std::fs::read(env.path("bokforing/journal.se")).unwrap();
+    let text = String::from_utf8_lossy(&sie4);
+    assert!(text.contains("#VER \"V\" \"1\" 20260104 \"Kassa 1, Z-Rapport #1\"\r\n{\r\n#TRANS 1580 {} 1250.00\r\n"));
+    assert!(text.contains("#VER \"V\" \"2\" 20260105 \"Kassa 1, Z-Rapport #2\""));
+    assert!(text.contains("#FNAMN \"Testbutiken AB\"\r\n"));
+    let konto = dinkassa::encode_pc8("#KONTO 3001 \"Försäljning 25%\"\r\n");
+    assert!(sie4.windows(konto.len()).any(|w| w == konto));
+
+    // The journal is checked for booked Z-reports like Bokio
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--yes", "--ledger", "bokforing"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("0 Z-Rapporter importerades"));
+    assert_eq!(read_journal(&env)["entries"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn reverses_entry_in_ledger() {
+    let env = TestEnv::new();
+    import_all(&env, &["--ledger", "bokforing"]);
+    let mut cmd = env.command();
+    cmd.arg("reverse").args(PERIOD).args(["1", "--yes"]).env("ESBOKIO_LEDGER", "bokforing");
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Verifikat V1 vändes med verifikat V3"));
+
+    let journal = read_journal(&env);
+    let entries = journal["entries"].as_array().unwrap();
+    assert_eq!(entries[0]["reversedByJournalEntryId"], "3");
+    assert_eq!(entries[2]["title"], "Vändning av V1");
+    assert_eq!(entries[2]["items"][0]["credit"], 1250.0);
+    let sie4 = std::fs::read(env.path("bokforing/journal.se")).unwrap();
+    let vandning = dinkassa::encode_pc8("\"3\" 20260104 \"Vändning av V1\"\r\n{\r\n#TRANS 1580 {} -1250.00");
+    assert!(sie4.windows(vandning.len()).any(|w| w == vandning));
+
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--yes", "--ledger", "bokforing"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 Z-Rapporter importerades"));
+    assert!(env.bokio.requests().is_empty());
+}
+
+#[test]
+fn reports_missing_entry_in_ledger() {
+    let env = TestEnv::new();
+    import_all(&env, &["--ledger", "bokforing"]);
+    let mut journal = read_journal(&env);
+    journal["entries"].as_array_mut().unwrap().remove(1);
+    std::fs::write(env.path("bokforing/journal.json"), serde_json::to_vec(&journal).unwrap()).unwrap();
+
+    let mut cmd = env.command();
+    cmd.args(["reconcile", "--ledger", "bokforing"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("K1:2 (2026-01-05): verifikat V2 finns inte i bokföringen i bokforing"));
+
+    // The removed entry's number is not used again
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--yes", "--ledger", "bokforing"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let journal = read_journal(&env);
+    assert_eq!(journal["entries"][1]["journalEntryNumber"], "V3");
+    assert_eq!(journal["entries"][1]["title"], "Kassa 1, Z-Rapport #2");
+}
+
+#[test]
+fn maps_accounts_for_company_with_ledger() {
+    let env = TestEnv::new();
+    let rule = format!("[[rule]]\nfrom = \"3001\"\nto = \"3002\"\ncompany = \"{}\"\n", bokio::COMPANY_ID);
+    std::fs::write(env.path("konton.toml"), rule).unwrap();
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--ledger", "bokforing", "--account-map", "konton.toml"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let journal = read_journal(&env);
+    assert_eq!(journal["entries"][0]["items"][2]["account"], 3002);
+    // Recorded under the ledger, not the Bokio company
+    let state: Value =
+        serde_json::from_slice(&std::fs::read(env.path("data/esbokio/state.json")).unwrap()).unwrap();
+    let company_id = state["imports"][0]["company_id"].as_str().unwrap();
+    assert!(company_id.ends_with("bokforing"), "{}", company_id);
+}
+
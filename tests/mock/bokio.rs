@@ -0,0 +1,342 @@
+//! Mock of the Bokio API keeping journal entries and uploads in memory.
+
+use super::{Failures, MockRequest, MockServer, Reply, fixture, json, status};
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+
+pub const TOKEN: &str = "bokio-token";
+pub const COMPANY_ID: &str = "company-1";
+
+#[derive(Clone, Debug)]
+pub struct Upload {
+    pub id: String,
+    pub journal_entry_id: String,
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+pub struct BokioState {
+    /// Journal entries in the order they were created.
+    pub journal_entries: Vec<Value>,
+    pub chart_of_accounts: Vec<Value>,
+    pub uploads: Vec<Upload>,
+    pub requests: Vec<String>,
+    pub failures: Failures,
+    /// Number of journal entries to create but answer 502 for, as if the response was lost.
+    pub lose_created_entries: usize,
+}
+
+pub struct BokioMock {
+    _server: MockServer,
+    pub url: String,
+    pub state: Arc<Mutex<BokioState>>,
+}
+
+impl BokioMock {
+    pub fn start() -> Self {
+        let fixtures = fixture("bokio.json");
+        let state = Arc::new(Mutex::new(BokioState {
+            journal_entries: fixtures["journalEntries"].as_array().unwrap().clone(),
+            chart_of_accounts: fixtures["chartOfAccounts"].as_array().unwrap().clone(),
+            uploads: Vec::new(),
+            requests: Vec::new(),
+            failures: Failures::default(),
+            lose_created_entries: 0,
+        }));
+        let handler_state = state.clone();
+        let server = MockServer::start(move |req| handle(&mut handler_state.lock().unwrap(), req));
+        let url = format!("{}/v1", server.url);
+        BokioMock {
+            _server: server,
+            url,
+            state,
+        }
+    }
+
+    pub fn journal_entries(&self) -> Vec<Value> {
+        self.state.lock().unwrap().journal_entries.clone()
+    }
+
+    /// Journal entries whose title starts with "Kassa".
+    pub fn kassa_entries(&self) -> Vec<Value> {
+        self.journal_entries()
+            .into_iter()
+            .filter(|e| e["title"].as_str().unwrap().starts_with("Kassa"))
+            .collect()
+    }
+
+    pub fn uploads(&self) -> Vec<Upload> {
+        self.state.lock().unwrap().uploads.clone()
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Adds an existing journal entry as if it had been booked earlier.
+    pub fn add_journal_entry(&self, title: &str, date: &str, items: &[(i32, f64, f64)]) -> String {
+        let mut state = self.state.lock().unwrap();
+        let entry = new_journal_entry(&state, title, date, items);
+        let id = entry["id"].as_str().unwrap().to_string();
+        state.journal_entries.push(entry);
+        id
+    }
+}
+
+fn new_journal_entry(state: &BokioState, title: &str, date: &str, items: &[(i32, f64, f64)]) -> Value {
+    let number = state.journal_entries.len() + 1;
+    let items: Vec<Value> = items
+        .iter()
+        .enumerate()
+        .map(|(i, (account, debit, credit))| {
+            json!({ "id": i + 1, "account": account, "debit": debit, "credit": credit })
+        })
+        .collect();
+    json!({
+        "id": format!("je-{}", number),
+        "title": title,
+        "journalEntryNumber": format!("V{}", number),
+        "date": date,
+        "items": items,
+        "reversingJournalEntryId": null,
+        "reversedByJournalEntryId": null,
+    })
+}
+
+fn handle(state: &mut BokioState, req: MockRequest) -> Reply {
+    state.requests.push(format!("{} {}", req.method, req.path));
+    if req.header("Authorization") != Some(&format!("Bearer {}", TOKEN)) {
+        return json(401, &json!({ "code": "unauthorized", "message": "Invalid token" }));
+    }
+
+    if let Some(reply) = state.failures.take(&req) {
+        return reply;
+    }
+
+    let Some(path) = req.path.strip_prefix(&format!("/v1/companies/{}/", COMPANY_ID)) else {
+        return status(404);
+    };
+
+    match (req.method.as_str(), path) {
+        ("GET", "journal-entries") => list_journal_entries(state, &req),
+        ("POST", "journal-entries") => create_journal_entry(state, &req),
+        ("GET", "uploads") => list_uploads(state, &req),
+        ("POST", "uploads") => upload(state, &req),
+        ("GET", "chart-of-accounts") => json(200, &Value::Array(state.chart_of_accounts.clone())),
+        ("POST", p) if p.starts_with("journal-entries/") && p.ends_with("/reverse") => {
+            reverse_journal_entry(state, &p["journal-entries/".len()..p.len() - "/reverse".len()])
+        }
+        ("GET", p) if p.starts_with("journal-entries/") => get_journal_entry(state, &p["journal-entries/".len()..]),
+        _ => status(404),
+    }
+}
+
+fn not_found() -> Reply {
+    json(
+        404,
+        &json!({ "code": "not-found", "message": "Journal entry not found" }),
+    )
+}
+
+fn find_journal_entry(state: &BokioState, id: &str) -> Option<usize> {
+    state.journal_entries.iter().position(|e| e["id"] == id)
+}
+
+fn get_journal_entry(state: &BokioState, id: &str) -> Reply {
+    match find_journal_entry(state, id) {
+        Some(i) => json(200, &state.journal_entries[i]),
+        None => not_found(),
+    }
+}
+
+fn reverse_journal_entry(state: &mut BokioState, id: &str) -> Reply {
+    let Some(i) = find_journal_entry(state, id) else {
+        return not_found();
+    };
+    let original = state.journal_entries[i].clone();
+    if !original["reversedByJournalEntryId"].is_null() {
+        return json(
+            400,
+            &json!({ "code": "validation-error", "message": "Journal entry is already reversed" }),
+        );
+    }
+
+    let items: Vec<(i32, f64, f64)> = original["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| {
+            (
+                i["account"].as_i64().unwrap() as i32,
+                i["credit"].as_f64().unwrap(),
+                i["debit"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    let title = format!("Vändning av {}", original["journalEntryNumber"].as_str().unwrap());
+    let mut entry = new_journal_entry(state, &title, original["date"].as_str().unwrap(), &items);
+    entry["reversingJournalEntryId"] = original["id"].clone();
+    state.journal_entries[i]["reversedByJournalEntryId"] = entry["id"].clone();
+    state.journal_entries.push(entry.clone());
+    json(200, &entry)
+}
+
+fn list_journal_entries(state: &BokioState, req: &MockRequest) -> Reply {
+    let page: usize = req.query("page").parse().unwrap_or(1);
+    let page_size: usize = req.query("pageSize").parse().unwrap_or(25);
+    let mut entries = state.journal_entries.clone();
+    // Newest first, like Bokio
+    entries.reverse();
+    entries.sort_by(|a, b| b["date"].as_str().cmp(&a["date"].as_str()));
+    let total_pages = entries.len().div_ceil(page_size);
+    let items: Vec<Value> = entries
+        .into_iter()
+        .skip((page - 1) * page_size)
+        .take(page_size)
+        .collect();
+    json(
+        200,
+        &json!({
+            "totalItems": state.journal_entries.len(),
+            "totalPages": total_pages,
+            "currentPage": page,
+            "items": items,
+        }),
+    )
+}
+
+fn create_journal_entry(state: &mut BokioState, req: &MockRequest) -> Reply {
+    let body = req.json();
+    let (Some(title), Some(date), Some(items)) =
+        (body["title"].as_str(), body["date"].as_str(), body["items"].as_array())
+    else {
+        return json(
+            400,
+            &json!({ "code": "validation-error", "message": "Invalid journal entry" }),
+        );
+    };
+
+    let items: Vec<(i32, f64, f64)> = items
+        .iter()
+        .map(|i| {
+            (
+                i["account"].as_i64().unwrap() as i32,
+                i["debit"].as_f64().unwrap(),
+                i["credit"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    let debit: f64 = items.iter().map(|i| i.1).sum();
+    let credit: f64 = items.iter().map(|i| i.2).sum();
+    if (debit - credit).abs() > 0.001 {
+        return json(
+            400,
+            &json!({ "code": "validation-error", "message": "Journal entry is not balanced" }),
+        );
+    }
+
+    let entry = new_journal_entry(state, title, date, &items);
+    state.journal_entries.push(entry.clone());
+    if state.lose_created_entries > 0 {
+        state.lose_created_entries -= 1;
+        return status(502);
+    }
+    json(200, &entry)
+}
+
+fn upload(state: &mut BokioState, req: &MockRequest) -> Reply {
+    let content_type = req.header("Content-Type").unwrap_or_default();
+    let Some(boundary) = content_type.split("boundary=").nth(1) else {
+        return status(400);
+    };
+
+    let mut upload = Upload {
+        id: format!("upload-{}", state.uploads.len() + 1),
+        journal_entry_id: String::new(),
+        filename: String::new(),
+        content_type: String::new(),
+        data: Vec::new(),
+    };
+    let delimiter = format!("--{}", boundary).into_bytes();
+    for part in split(&req.body, &delimiter) {
+        let Some(pos) = find(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&part[..pos]);
+        let data = part[pos + 4..].strip_suffix(b"\r\n").unwrap_or(&part[pos + 4..]);
+        if headers.contains("name=\"journalEntryId\"") {
+            upload.journal_entry_id = String::from_utf8_lossy(data).to_string();
+        } else if headers.contains("name=\"file\"") {
+            upload.filename = headers
+                .split("filename=\"")
+                .nth(1)
+                .and_then(|s| s.split('"').next())
+                .unwrap_or_default()
+                .to_string();
+            upload.content_type = headers
+                .lines()
+                .find_map(|l| l.strip_prefix("Content-Type: "))
+                .unwrap_or_default()
+                .to_string();
+            upload.data = data.to_vec();
+        }
+    }
+
+    if find_journal_entry(state, &upload.journal_entry_id).is_none() {
+        return not_found();
+    }
+
+    let reply = json!({
+        "id": upload.id,
+        "description": upload.filename,
+        "contentType": upload.content_type,
+        "journalEntryId": upload.journal_entry_id,
+    });
+    state.uploads.push(upload);
+    json(200, &reply)
+}
+
+fn list_uploads(state: &BokioState, req: &MockRequest) -> Reply {
+    let page: usize = req.query("page").parse().unwrap_or(1);
+    let page_size: usize = req.query("pageSize").parse().unwrap_or(25);
+    let journal_entry_id = req.query("query").strip_prefix("journalEntryId==");
+    let uploads: Vec<Value> = state
+        .uploads
+        .iter()
+        .filter(|u| journal_entry_id.is_none_or(|id| u.journal_entry_id == id))
+        .map(|u| {
+            json!({
+                "id": u.id,
+                "description": u.filename,
+                "contentType": u.content_type,
+                "journalEntryId": u.journal_entry_id,
+            })
+        })
+        .collect();
+    let total_items = uploads.len();
+    let items: Vec<Value> = uploads.into_iter().skip((page - 1) * page_size).take(page_size).collect();
+    json(
+        200,
+        &json!({
+            "totalItems": total_items,
+            "totalPages": total_items.div_ceil(page_size),
+            "currentPage": page,
+            "items": items,
+        }),
+    )
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn split<'a>(mut data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    while let Some(pos) = find(data, delimiter) {
+        parts.push(&data[..pos]);
+        data = &data[pos + delimiter.len()..];
+    }
+    parts.push(data);
+    parts
+}
@@ -0,0 +1,279 @@
+//! Mock of the dinkassa.se API serving the reports in `tests/fixtures/dinkassa.json`.
+
+use super::{Failures, MockRequest, MockServer, Reply, bytes, fixture, json, status};
+use serde_json::{Value, json};
+use tiny_http::Header;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub const USERNAME: &str = "kassa@example.com";
+pub const PASSWORD: &str = "hemligt";
+pub const INTEGRATOR_ID: &str = "integrator-1";
+pub const WEB_INTEGRATOR_ID: &str = "cc7c4035-ce21-40a6-95e2-a39a641a1c27";
+
+pub struct DinKassaState {
+    pub fixtures: Value,
+    /// Valid session ids and the number of requests each one has left.
+    pub sessions: Vec<(String, Option<usize>)>,
+    /// Number of requests a new session is valid for, `None` = until the test ends.
+    pub session_lifetime: Option<usize>,
+    pub authentications: usize,
+    pub requests: Vec<String>,
+    pub failures: Failures,
+    /// SIE4 files served instead of the generated ones, by Z-report id.
+    pub sie4_files: HashMap<String, Vec<u8>>,
+}
+
+pub struct DinKassaMock {
+    _server: MockServer,
+    pub url: String,
+    pub state: Arc<Mutex<DinKassaState>>,
+}
+
+impl DinKassaMock {
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(DinKassaState {
+            fixtures: fixture("dinkassa.json"),
+            sessions: Vec::new(),
+            session_lifetime: None,
+            authentications: 0,
+            requests: Vec::new(),
+            failures: Failures::default(),
+            sie4_files: HashMap::new(),
+        }));
+        let handler_state = state.clone();
+        let server = MockServer::start(move |req| handle(&mut handler_state.lock().unwrap(), req));
+        let url = format!("{}/api", server.url);
+        DinKassaMock {
+            _server: server,
+            url,
+            state,
+        }
+    }
+
+    pub fn authentications(&self) -> usize {
+        self.state.lock().unwrap().authentications
+    }
+
+    pub fn set_sie4(&self, report_id: &str, content: &str) {
+        self.state.lock().unwrap().sie4_files.insert(report_id.to_string(), encode_pc8(content));
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+/// Returns the machine the request is authorized for, `None` for a web user session
+/// that may access all machines.
+fn authorize(state: &mut DinKassaState, req: &MockRequest) -> Result<Option<String>, Reply> {
+    if let (Some(machine_id), Some(machine_key)) = (req.header("MachineId"), req.header("MachineKey")) {
+        let valid = req.header("IntegratorId") == Some(INTEGRATOR_ID)
+            && state.fixtures["MachineKeys"][machine_id].as_str() == Some(machine_key);
+        return if valid {
+            Ok(Some(machine_id.to_string()))
+        } else {
+            Err(status(401))
+        };
+    }
+
+    if req.header("IntegratorId") != Some(WEB_INTEGRATOR_ID) {
+        return Err(status(401));
+    }
+
+    // The session cookie has to belong to the same session as the header
+    let session_id = req.header("SessionId").unwrap_or_default();
+    let cookie = format!("session={}", session_id);
+    if !req.header("Cookie").unwrap_or_default().split("; ").any(|c| c == cookie) {
+        return Err(status(401));
+    }
+    let Some(pos) = state.sessions.iter().position(|(id, _)| id == session_id) else {
+        return Err(status(401));
+    };
+    match &mut state.sessions[pos].1 {
+        Some(0) => {
+            state.sessions.remove(pos);
+            Err(status(401))
+        }
+        Some(n) => {
+            *n -= 1;
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+fn authenticate(state: &mut DinKassaState, req: &MockRequest) -> Reply {
+    let body = req.json();
+    if req.query("type") != "2"
+        || req.header("IntegratorId") != Some(WEB_INTEGRATOR_ID)
+        || body["Username"] != USERNAME
+        || body["Password"] != PASSWORD
+    {
+        return status(401);
+    }
+
+    state.authentications += 1;
+    let session_id = format!("session-{}", state.authentications);
+    state.sessions.push((session_id.clone(), state.session_lifetime));
+    json(
+        200,
+        &json!({
+            "Id": session_id,
+            "Type": 2,
+            "WebUserId": "web-user-1",
+            "ExpiresDateTime": "2099-12-31T23:59:59",
+        }),
+    )
+    .with_header(Header::from_bytes("Set-Cookie", format!("session={}; Path=/; Max-Age=86400", session_id)).unwrap())
+}
+
+fn handle(state: &mut DinKassaState, req: MockRequest) -> Reply {
+    state.requests.push(format!("{} {}", req.method, req.path));
+    if let Some(reply) = state.failures.take(&req) {
+        return reply;
+    }
+
+    let Some(path) = req.path.strip_prefix("/api/") else {
+        return status(404);
+    };
+
+    if req.method == "POST" && path == "session/Authenticate" {
+        return authenticate(state, &req);
+    }
+
+    let authorized_machine = match authorize(state, &req) {
+        Ok(machine) => machine,
+        Err(reply) => return reply,
+    };
+    let fixtures = &state.fixtures;
+    let machine_id = authorized_machine
+        .clone()
+        .unwrap_or_else(|| req.query("machineId").to_string());
+    if authorized_machine.is_some() && !req.query("machineId").is_empty() && req.query("machineId") != machine_id {
+        return status(403);
+    }
+
+    let parts: Vec<&str> = path.split('/').collect();
+    match (req.method.as_str(), &parts[..]) {
+        ("GET", ["settings"]) => json(200, &fixtures["Settings"]),
+        ("GET", ["machine"]) => {
+            let items: Vec<&Value> = fixtures["Machines"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|m| authorized_machine.is_none() || m["Id"] == machine_id.as_str())
+                .collect();
+            json(200, &json!({ "ItemCountFetched": items.len(), "Items": items }))
+        }
+        ("GET", ["reports", "get-z-reports"]) => {
+            let start = &req.query("startDateTime")[..10];
+            let end = &req.query("endDateTime")[..10];
+            let items = filter_dates(&fixtures["ZReports"][&machine_id], "DateTime", start, end);
+            json(200, &json!({ "ItemCountFetched": items.len(), "Items": items }))
+        }
+        ("GET", ["reports", "download-z-report-by-date", "json"]) => {
+            let items = filter_dates(
+                &fixtures["SIEReports"][&machine_id],
+                "ReportDateTime",
+                req.query("startDate"),
+                req.query("endDate"),
+            );
+            json(200, &json!({ "ZReports": items }))
+        }
+        ("GET", ["reports", "download-z-report", machine, id]) => match find_report(fixtures, machine, id) {
+            Some(_) => bytes(200, "application/pdf", pdf(id)),
+            None => status(404),
+        },
+        ("GET", ["reports", "download-z-report", machine, id, "sie4"]) => match find_report(fixtures, machine, id) {
+            Some(_) if state.sie4_files.contains_key(*id) => bytes(200, "text/plain", state.sie4_files[*id].clone()),
+            Some(report) => {
+                let sie = find_sie(fixtures, machine, report);
+                bytes(200, "text/plain", sie4(report, sie))
+            }
+            None => status(404),
+        },
+        _ => status(404),
+    }
+}
+
+fn filter_dates(items: &Value, field: &str, start: &str, end: &str) -> Vec<Value> {
+    items
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter(|item| {
+                    let date = &item[field].as_str().unwrap()[..10];
+                    start <= date && date <= end
+                })
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn find_report<'a>(fixtures: &'a Value, machine: &str, id: &str) -> Option<&'a Value> {
+    fixtures["ZReports"][machine].as_array()?.iter().find(|r| r["Id"] == id)
+}
+
+fn find_sie<'a>(fixtures: &'a Value, machine: &str, report: &Value) -> Option<&'a Value> {
+    let number = report["Number"].as_u64()?;
+    fixtures["SIEReports"][machine]
+        .as_array()?
+        .iter()
+        .find(|s| s["ZReport"].as_str().unwrap().ends_with(&format!(":{}", number)))
+}
+
+pub fn pdf(report_id: &str) -> Vec<u8> {
+    format!("%PDF-1.4\n% Z-Rapport {}\n%%EOF\n", report_id).into_bytes()
+}
+
+/// A SIE4 file with the same transactions as the JSON report.
+fn sie4(report: &Value, sie: Option<&Value>) -> Vec<u8> {
+    let date = report["DateTime"].as_str().unwrap()[..10].replace('-', "");
+    let mut out = String::new();
+    out.push_str("#FLAGGA 0\r\n#FORMAT PC8\r\n#SIETYP 4\r\n");
+    out.push_str("#PROGRAM \"ES Kassa\" 1.0\r\n");
+    out.push_str(&format!("#GEN {}\r\n", date));
+    out.push_str("#FNAMN \"Testbutiken AB\"\r\n");
+    if let Some(sie) = sie {
+        for acc in sie["Accounts"].as_array().unwrap() {
+            out.push_str(&format!(
+                "#KONTO {} \"{}\"\r\n",
+                acc["Number"].as_str().unwrap(),
+                acc["Description"].as_str().unwrap()
+            ));
+        }
+        out.push_str(&format!(
+            "#VER \"\" \"\" {} \"{}\"\r\n{{\r\n",
+            date,
+            sie["ZReport"].as_str().unwrap()
+        ));
+        for acc in sie["Accounts"].as_array().unwrap() {
+            out.push_str(&format!(
+                "   #TRANS {} {{}} {:.2}\r\n",
+                acc["Number"].as_str().unwrap(),
+                acc["Amount"].as_f64().unwrap()
+            ));
+        }
+        out.push_str("}\r\n");
+    }
+    encode_pc8(&out)
+}
+
+/// Encodes the few non-ASCII characters used in the fixtures as PC8 (code page 437).
+pub fn encode_pc8(s: &str) -> Vec<u8> {
+    s.chars()
+        .map(|c| match c {
+            'å' => 0x86,
+            'ä' => 0x84,
+            'ö' => 0x94,
+            'Å' => 0x8f,
+            'Ä' => 0x8e,
+            'Ö' => 0x99,
+            c if c.is_ascii() => c as u8,
+            _ => b'?',
+        })
+        .collect()
+}
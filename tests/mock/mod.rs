@@ -0,0 +1,221 @@
+//! Local stand-ins for the dinkassa.se and Bokio APIs used by the end-to-end tests.
+
+#![allow(dead_code)]
+
+pub mod bokio;
+pub mod dinkassa;
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tiny_http::{Header, Server};
+
+pub type Reply = tiny_http::Response<Cursor<Vec<u8>>>;
+
+#[derive(Debug)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> &str {
+        self.query.get(name).map_or("", |v| v.as_str())
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+}
+
+pub fn json(status: u16, value: &Value) -> Reply {
+    bytes(status, "application/json", serde_json::to_vec(value).unwrap())
+}
+
+pub fn bytes(status: u16, content_type: &str, data: Vec<u8>) -> Reply {
+    tiny_http::Response::from_data(data)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", content_type).unwrap())
+}
+
+pub fn status(status: u16) -> Reply {
+    bytes(status, "text/plain", Vec::new())
+}
+
+/// Transient failures to answer with before handling requests normally.
+#[derive(Default)]
+pub struct Failures(Vec<(String, u16)>);
+
+impl Failures {
+    /// Fails the next `count` requests whose path contains `path` with `status`.
+    pub fn add(&mut self, path: &str, status: u16, count: usize) {
+        for _ in 0..count {
+            self.0.push((path.to_string(), status));
+        }
+    }
+
+    pub fn take(&mut self, req: &MockRequest) -> Option<Reply> {
+        let pos = self.0.iter().position(|(path, _)| req.path.contains(path.as_str()))?;
+        let (_, code) = self.0.remove(pos);
+        Some(status(code).with_header(Header::from_bytes("Retry-After", "0").unwrap()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// HTTP server running on a random local port until dropped.
+pub struct MockServer {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+    pub url: String,
+}
+
+impl MockServer {
+    pub fn start(handler: impl Fn(MockRequest) -> Reply + Send + 'static) -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let port = server.server_addr().to_ip().unwrap().port();
+        let incoming = server.clone();
+        let thread = std::thread::spawn(move || {
+            for mut req in incoming.incoming_requests() {
+                let (path, query) = match req.url().split_once('?') {
+                    Some((path, query)) => (path.to_string(), parse_query(query)),
+                    None => (req.url().to_string(), HashMap::new()),
+                };
+                let mut body = Vec::new();
+                req.as_reader().read_to_end(&mut body).ok();
+                let mock_req = MockRequest {
+                    method: req.method().as_str().to_string(),
+                    path,
+                    query,
+                    headers: req
+                        .headers()
+                        .iter()
+                        .map(|h| (h.field.as_str().to_string(), h.value.as_str().to_string()))
+                        .collect(),
+                    body,
+                };
+                req.respond(handler(mock_req)).ok();
+            }
+        });
+
+        MockServer {
+            server,
+            thread: Some(thread),
+            url: format!("http://127.0.0.1:{}", port),
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), urlencoding::decode(v).unwrap().to_string()))
+        .collect()
+}
+
+pub fn fixture(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+/// Both mock servers and a scratch directory used as working directory and home.
+pub struct TestEnv {
+    pub dinkassa: dinkassa::DinKassaMock,
+    pub bokio: bokio::BokioMock,
+    pub dir: tempfile::TempDir,
+}
+
+impl TestEnv {
+    pub fn new() -> Self {
+        TestEnv {
+            dinkassa: dinkassa::DinKassaMock::start(),
+            bokio: bokio::BokioMock::start(),
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// The esbokio binary configured against the mock servers using a web login.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_esbokio"));
+        cmd.current_dir(self.dir.path())
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.dir.path())
+            .env("XDG_CACHE_HOME", self.path("cache"))
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_DATA_HOME", self.path("data"))
+            .env("DINKASSA_API_URL", &self.dinkassa.url)
+            .env("DINKASSA_USERNAME", dinkassa::USERNAME)
+            .env("DINKASSA_PASSWORD", dinkassa::PASSWORD)
+            .env("BOKIO_API_URL", &self.bokio.url)
+            .env("BOKIO_API_TOKEN", bokio::TOKEN)
+            .env("BOKIO_COMPANY_ID", bokio::COMPANY_ID);
+        cmd
+    }
+}
+
+pub fn run(mut cmd: Command, stdin: &str) -> Output {
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = cmd.spawn().unwrap();
+    {
+        use std::io::Write;
+        let mut input = child.stdin.take().unwrap();
+        input.write_all(stdin.as_bytes()).unwrap();
+    }
+    let output = child.wait_with_output().unwrap();
+    if !output.status.success() {
+        eprintln!("stdout:\n{}", String::from_utf8_lossy(&output.stdout));
+        eprintln!("stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+    }
+    output
+}
+
+/// The dates of the two Z-reports of Kassa 1 in the fixtures.
+pub const PERIOD: [&str; 4] = ["--start", "2026-01-04", "--end", "2026-01-05"];
+
+/// Books both Z-reports of Kassa 1, `args` are added to the command line.
+pub fn import_all(env: &TestEnv, args: &[&str]) {
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes").args(args);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("2 Z-Rapporter importerades"));
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
@@ -0,0 +1,227 @@
+//! End-to-end tests for importing Z-reports from SIE4 files and exporting them as SIE4.
+
+mod mock;
+
+use esbokio::sie;
+use mock::{PERIOD, TestEnv, dinkassa, run, stderr, stdout};
+use serde_json::json;
+
+/// Saves the SIE4 files and PDFs of Kassa 1 in `export`.
+fn export(env: &TestEnv) {
+    let mut cmd = env.command();
+    cmd.arg("export").args(PERIOD).args(["--output", "export"]);
+    assert!(run(cmd, "").status.success());
+}
+
+#[test]
+fn imports_sie_files_without_dinkassa() {
+    let env = TestEnv::new();
+    export(&env);
+    let requests = env.dinkassa.requests().len();
+
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "export", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("2 Z-Rapporter importerades"));
+    assert_eq!(env.dinkassa.requests().len(), requests);
+
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["title"], "Kassa 1, Z-Rapport #1");
+    assert_eq!(entries[0]["date"], "2026-01-04");
+    assert_eq!(
+        entries[0]["items"],
+        json!([
+            { "id": 1, "account": 1580, "debit": 1250.0, "credit": 0.0 },
+            { "id": 2, "account": 1910, "debit": 187.5, "credit": 0.0 },
+            { "id": 3, "account": 3001, "debit": 0.0, "credit": 1150.0 },
+            { "id": 4, "account": 2611, "debit": 0.0, "credit": 287.5 },
+        ])
+    );
+
+    let uploads = env.bokio.uploads();
+    assert_eq!(uploads.len(), 2);
+    assert_eq!(uploads[0].journal_entry_id, entries[0]["id"]);
+    assert_eq!(uploads[0].filename, "Z1_Kassa 1_Testbutiken AB.pdf");
+    assert_eq!(uploads[0].data, dinkassa::pdf("z-1-1"));
+}
+
+#[test]
+fn skips_booked_reports_in_sie_files() {
+    let env = TestEnv::new();
+    export(&env);
+    let mut cmd = env.command();
+    cmd.args(PERIOD).args(["--only", "1"]);
+    assert!(run(cmd, "").status.success());
+
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "export", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("K1:1 (2026-01-04): bokförd som V2"));
+    assert!(out.contains("1 Z-Rapporter importerades"));
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+}
+
+#[test]
+fn imports_sie_file_without_pdf() {
+    let env = TestEnv::new();
+    export(&env);
+    let fil = env.path("export/Z2_Kassa 1_Testbutiken AB.si");
+
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "--dry-run"]).arg(&fil);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Kassa 1, Z-Rapport #2 2026-01-05"));
+    assert!(env.bokio.kassa_entries().is_empty());
+
+    std::fs::remove_file(env.path("export/Z2_Kassa 1_Testbutiken AB.pdf")).unwrap();
+    let mut cmd = env.command();
+    cmd.arg("import-sie").arg(&fil);
+    let output = run(cmd, "j\n");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Inget underlag"));
+    assert_eq!(env.bokio.kassa_entries().len(), 1);
+    assert!(env.bokio.uploads().is_empty());
+}
+
+#[test]
+fn fails_on_unreadable_sie_file() {
+    let env = TestEnv::new();
+    std::fs::write(env.path("trasig.si"), "#FLAGGA 0\r\n#VER \"\" \"\" 20260104 \"K1:1\"\r\n#TRANS 1910 {} 1\r\n")
+        .unwrap();
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "trasig.si", "--yes"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("trasig.si, SIE4 rad 3: { saknas efter #VER"));
+}
+
+#[test]
+fn books_report_in_several_files_once() {
+    let env = TestEnv::new();
+    export(&env);
+    let mut cmd = env.command();
+    cmd.arg("export-sie").args(PERIOD).args(["--output", "export/z.se"]);
+    assert!(run(cmd, "").status.success());
+
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "export", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("2 Z-Rapporter i SIE4-filer"));
+    assert!(stderr(&output).contains(
+        "Varning: Z-Rapport K1:1 (2026-01-04) finns även i export/Z1_Kassa 1_Testbutiken AB.si, \
+         hoppar över den i export/z.se"
+    ));
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+
+    // Refused when the files differ
+    let env = TestEnv::new();
+    export(&env);
+    let mut cmd = env.command();
+    cmd.arg("export-sie").args(PERIOD).args(["--output", "export/z.se"]);
+    assert!(run(cmd, "").status.success());
+    let fil = env.path("export/Z1_Kassa 1_Testbutiken AB.si");
+    let sie4: String = std::fs::read(&fil).unwrap().into_iter().map(char::from).collect();
+    let sie4: Vec<u8> = sie4.replace("1250.00", "1240.00").chars().map(|c| c as u8).collect();
+    std::fs::write(&fil, sie4).unwrap();
+
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "export", "--yes"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(6));
+    assert!(stderr(&output).contains(
+        "Z-Rapport K1:1 (2026-01-04) har olika belopp i export/Z1_Kassa 1_Testbutiken AB.si och export/z.se"
+    ));
+    assert!(env.bokio.kassa_entries().is_empty());
+}
+
+#[test]
+fn exports_sie_file_without_bokio() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("export-sie").args(PERIOD).args(["--output", "z.se"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("2 verifikat sparades i z.se"));
+    assert!(env.bokio.requests().is_empty());
+
+    let data = std::fs::read(env.path("z.se")).unwrap();
+    let text = String::from_utf8_lossy(&data);
+    assert!(text.starts_with("#FLAGGA 0\r\n#KSUMMA\r\n#PROGRAM \"esbokio\""));
+    assert!(text.contains("#FNAMN \"Testbutiken AB\"\r\n"));
+    assert!(text.contains("#VER \"\" \"\" 20260104 \"Kassa 1, Z-Rapport #1\"\r\n{\r\n#TRANS 1580 {} 1250.00\r\n"));
+    assert!(text.contains("#TRANS 3001 {} -1150.00\r\n"));
+    assert!(text.contains("#VER \"\" \"\" 20260105 \"Kassa 1, Z-Rapport #2\""));
+    assert!(text.lines().last().unwrap().starts_with("#KSUMMA "));
+    let konto = dinkassa::encode_pc8("#KONTO 3001 \"Försäljning 25%\"");
+    assert!(data.windows(konto.len()).any(|w| w == konto));
+
+    // The file can be booked later, with the same titles as a normal import
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "z.se", "--yes"]);
+    let output = run(cmd, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("Kontrollsumman"));
+    let entries = env.bokio.kassa_entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1]["title"], "Kassa 1, Z-Rapport #2");
+
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("0 Z-Rapporter importerades"));
+}
+
+#[test]
+fn warns_about_wrong_checksum() {
+    let env = TestEnv::new();
+    let mut cmd = env.command();
+    cmd.arg("export-sie").args(["--date", "2026-01-04", "--output", "z.se"]);
+    assert!(run(cmd, "").status.success());
+    let mut data = std::fs::read(env.path("z.se")).unwrap();
+    let pos = data.windows(14).position(|w| w == b"1910 {} 187.50").unwrap();
+    data.splice(pos..pos + 14, b"1910 {} 178.50".iter().copied());
+    std::fs::write(env.path("z.se"), data).unwrap();
+
+    let mut cmd = env.command();
+    cmd.args(["import-sie", "z.se", "--dry-run"]);
+    let output = run(cmd, "");
+    assert!(stderr(&output).contains("Varning: Kontrollsumman (#KSUMMA) i z.se stämmer inte"));
+}
+
+#[test]
+fn writes_company_name_when_missing() {
+    let env = TestEnv::new();
+    env.dinkassa.state.lock().unwrap().fixtures["Machines"][0]["CustomerName"] = json!("");
+    let mut cmd = env.command();
+    cmd.arg("export-sie").args(["--date", "2026-01-04", "--output", "z.se"]);
+    assert!(run(cmd, "").status.success());
+    let text = String::from_utf8_lossy(&std::fs::read(env.path("z.se")).unwrap()).to_string();
+    assert!(text.contains("#SIETYP 4\r\n#FNAMN \"\"\r\n"));
+}
+
+/// A file with the checksum computed with another CRC-32 implementation (Python's
+/// zlib.crc32) over the labels and fields in PC8, as described in the SIE 4B specification.
+const SIE4_KSUMMA: &str = "#FLAGGA 0\r\n#KSUMMA\r\n#PROGRAM \"Bokföring\" 1.0\r\n#FORMAT PC8\r\n#SIETYP 4\r\n\
+    #FNAMN \"Övningsbolaget \\\"Syd\\\" AB\"\r\n#KONTO 1910 Kassa\r\n#VER A 1 20260104 \"Försäljning\"\r\n{\r\n\
+    #TRANS 1910 {1 \"10\"} 100.00\r\n#TRANS 3001 {} -100.00\r\n}\r\n#KSUMMA 99175291\r\n";
+
+#[test]
+fn checks_known_checksum() {
+    let data = dinkassa::encode_pc8(SIE4_KSUMMA);
+    let parsed = sie::parse(&data).unwrap();
+    assert_eq!(parsed.checksum_ok, Some(true));
+
+    // Written again with the same checksum, although quoted differently
+    let written = sie::write(&parsed);
+    assert!(String::from_utf8_lossy(&written).ends_with("}\r\n#KSUMMA 99175291\r\n"));
+
+    let changed = dinkassa::encode_pc8(&SIE4_KSUMMA.replace("Övningsbolaget", "Ovningsbolaget"));
+    assert_eq!(sie::parse(&changed).unwrap().checksum_ok, Some(false));
+}
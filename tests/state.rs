@@ -0,0 +1,195 @@
+//! End-to-end tests for the local record of imported Z-reports.
+
+mod mock;
+
+use mock::{PERIOD, TestEnv, import_all, run, stdout};
+use serde_json::Value;
+
+fn state(env: &TestEnv) -> Value {
+    serde_json::from_slice(&std::fs::read(env.path("data/esbokio/state.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn records_imports() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+
+    let imports = state(&env)["imports"].as_array().unwrap().clone();
+    assert_eq!(imports.len(), 2);
+    assert_eq!(imports[0]["company_id"], "company-1");
+    assert_eq!(imports[0]["machine_id"], "m-1");
+    assert_eq!(imports[0]["register"], 1);
+    assert_eq!(imports[0]["number"], 1);
+    assert_eq!(imports[0]["journal_entry_id"], "je-2");
+    assert_eq!(imports[0]["journal_entry_number"], "V2");
+    assert_eq!(imports[0]["uploads"], serde_json::json!(["upload-1"]));
+    assert_eq!(imports[0]["hash"].as_str().unwrap().len(), 64);
+    assert_eq!(imports[0]["steps"][3], "attachment_uploaded");
+    assert!(imports[0]["pdf"].is_null());
+}
+
+#[test]
+fn finds_imported_report_with_changed_title() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    env.bokio.state.lock().unwrap().journal_entries[1]["title"] = "Dagskassa 4/1".into();
+
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("2 Z-Rapporter redan importerade"));
+    assert_eq!(env.bokio.journal_entries().len(), 3);
+}
+
+#[test]
+fn warns_when_report_changed_after_import() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    env.dinkassa.state.lock().unwrap().fixtures["SIEReports"]["m-1"][0]["LastTransactionDateTime"] =
+        "2026-01-04T18:00:00".into();
+
+    let mut cmd = env.command();
+    cmd.arg("list").args(PERIOD);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Z-Rapport K1:1 (2026-01-04) har ändrats i dinkassa.se sedan den bokfördes som V2"));
+}
+
+#[test]
+fn reconcile_removes_deleted_journal_entries() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    env.bokio.state.lock().unwrap().journal_entries.retain(|e| e["id"] != "je-2");
+
+    let mut cmd = env.command();
+    cmd.arg("reconcile").args(PERIOD);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Z-Rapport K1:1 (2026-01-04): verifikat V2 finns inte i Bokio"));
+    let imports = state(&env)["imports"].as_array().unwrap().clone();
+    assert_eq!(imports.len(), 1);
+    assert_eq!(imports[0]["number"], 2);
+
+    // The Z-report can be imported again
+    let mut cmd = env.command();
+    cmd.args(PERIOD).arg("--yes");
+    assert!(stdout(&run(cmd, "")).contains("1 Z-Rapporter importerades"));
+    assert_eq!(env.bokio.kassa_entries().len(), 2);
+}
+
+#[test]
+fn reconcile_records_existing_journal_entries() {
+    let env = TestEnv::new();
+    env.bokio
+        .add_journal_entry("Kassa 1, Z-Rapport #1", "2026-01-04", &[(1580, 1250.0, 0.0)]);
+
+    let mut cmd = env.command();
+    cmd.arg("reconcile").args(PERIOD);
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("registreras med verifikat V2"));
+    let imports = state(&env)["imports"].as_array().unwrap().clone();
+    assert_eq!(imports.len(), 1);
+    assert_eq!(imports[0]["journal_entry_id"], "je-2");
+    assert_eq!(imports[0]["uploads"], serde_json::json!([]));
+}
+
+#[test]
+fn reverse_removes_record() {
+    let env = TestEnv::new();
+    import_all(&env, &[]);
+    let mut cmd = env.command();
+    cmd.arg("reverse").args(PERIOD).args(["1", "--yes"]);
+    assert!(run(cmd, "").status.success());
+
+    let imports = state(&env)["imports"].as_array().unwrap().clone();
+    assert_eq!(imports.len(), 1);
+    assert_eq!(imports[0]["number"], 2);
+}
+
+/// Imports the first Z-report with the upload of the PDF failing.
+fn import_without_upload(env: &TestEnv) {
+    env.bokio.state.lock().unwrap().failures.add("/uploads", 500, 1);
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--retries", "0", "--summary", "summary.json"]);
+    let output = run(cmd, "");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ladda upp det senare med esbokio resume"));
+    assert!(stdout(&output).contains("1 Z-Rapporter saknar underlag"));
+    assert_eq!(env.bokio.kassa_entries().len(), 1);
+    assert!(env.bokio.uploads().is_empty());
+
+    let import = state(env)["imports"][0].clone();
+    assert_eq!(import["steps"], serde_json::json!(["pdf_fetched", "sie4_fetched", "entry_created"]));
+    assert_eq!(import["status"], "attachment_missing");
+    assert_eq!(import["pdf"], "Z1_Kassa 1_Testbutiken AB.pdf");
+
+    let summary: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(env.path("summary.json")).unwrap()).unwrap();
+    assert_eq!(summary["imported"], 0);
+    assert_eq!(summary["attachment_missing"], 1);
+    assert_eq!(summary["reports"][0]["status"], "attachment_missing");
+    assert_eq!(summary["reports"][0]["journal_entry"], "V2");
+}
+
+#[test]
+fn resume_uploads_missing_attachment() {
+    let env = TestEnv::new();
+    import_without_upload(&env);
+    assert!(env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+
+    let mut cmd = env.command();
+    cmd.arg("resume");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Z-Rapport K1:1 (2026-01-04): laddar upp underlag till verifikat V2"));
+
+    let uploads = env.bokio.uploads();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].journal_entry_id, "je-2");
+    assert_eq!(uploads[0].filename, "Z1_Kassa 1_Testbutiken AB.pdf");
+    let import = state(&env)["imports"][0].clone();
+    assert_eq!(import["steps"][3], "attachment_uploaded");
+    assert_eq!(import["uploads"], serde_json::json!(["upload-1"]));
+    assert!(import["pdf"].is_null());
+    assert!(import["status"].is_null());
+
+    let mut cmd = env.command();
+    cmd.arg("resume");
+    let output = run(cmd, "");
+    assert!(stdout(&output).contains("Inga avbrutna importer"));
+}
+
+#[test]
+fn resume_fetches_pdf_again() {
+    let env = TestEnv::new();
+    import_without_upload(&env);
+    std::fs::remove_file(env.path("Z1_Kassa 1_Testbutiken AB.pdf")).unwrap();
+
+    let mut cmd = env.command();
+    cmd.arg("resume");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert_eq!(env.bokio.uploads()[0].data, mock::dinkassa::pdf("z-1-1"));
+    assert!(!env.path("Z1_Kassa 1_Testbutiken AB.pdf").exists());
+}
+
+#[test]
+fn resume_forgets_import_that_was_not_booked() {
+    let env = TestEnv::new();
+    // Stops after the PDF is fetched
+    env.dinkassa.state.lock().unwrap().failures.add("/sie4", 500, 1);
+    let mut cmd = env.command();
+    cmd.args(["--date", "2026-01-04", "--yes", "--retries", "0"]);
+    assert_eq!(run(cmd, "").status.code(), Some(3));
+    assert_eq!(state(&env)["imports"][0]["steps"], serde_json::json!(["pdf_fetched"]));
+
+    let mut cmd = env.command();
+    cmd.arg("resume");
+    let output = run(cmd, "");
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Z-Rapport K1:1 (2026-01-04): bokfördes inte, importera den igen"));
+    assert!(state(&env)["imports"].as_array().unwrap().is_empty());
+}